@@ -1,5 +1,6 @@
 use glam::Vec3;
-use std::collections::HashMap;
+use indexmap::IndexMap;
+use std::collections::{BTreeSet, HashMap};
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::mpsc::{self, Receiver, Sender};
@@ -14,17 +15,24 @@ use winit::{
 };
 
 use crate::camera::Action;
-use crate::loader::{FileLoader, MMCIFLoader, SDFLoader};
+use crate::ccd::ComponentDictionary;
+use crate::dssp;
+use crate::loader::{FileLoader, MMCIFLoader, PDBLoader, SDFLoader, XYZLoader};
 use crate::renderer::Renderer;
-use crate::shape::Shape;
-use crate::tessellate::{RenderStyle, Structure, Tessellator};
-use crate::ui::UIState;
+use crate::shader::MAX_COPIES;
+use crate::shape::ShapeGroup;
+use crate::tessellate::{
+    AltLocPolicy, ColorScheme, RenderStyle, Structure, Tessellator, ViewOptions,
+};
+use crate::ui::{RecordInfo, UIState};
 
 enum Message {
-    LoadFileRequest(PathBuf),
-    LoadSuccess,
-    TessRequest((RenderStyle, Vec3)),
-    TessResponse((Vec<Shape>, Vec3, Vec3)),
+    LoadFileRequest((PathBuf, bool)), // path, whether to add missing hydrogens
+    LoadSuccess(usize),               // number of records in the file
+    SelectRecord(usize),
+    RecordSelected(RecordInfo),
+    TessRequest((ViewOptions, Vec3)),
+    TessResponse((Vec<ShapeGroup>, Vec3, Vec3)),
     ErrResponse(String),
 }
 
@@ -32,32 +40,93 @@ enum Message {
 fn run_loading_thread(rx_loader: Receiver<Message>, tx_app: Sender<Message>) {
     let mut loaders: HashMap<String, Box<dyn FileLoader>> = HashMap::new();
     let mut tessellator = Tessellator::new().unwrap();
-    let mut structure = Structure::default();
+    // The dictionary is a separate download, so residues are left as they are without it
+    let ccd_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("data/mmcif/chemical-component-dictionary.cif");
+    let mut ccd = ComponentDictionary::open(&ccd_path).ok();
+    let mut structures: Vec<Structure> = Vec::new();
+    let mut current = 0;
 
     let mut handle_message = || -> Result<(), String> {
         match rx_loader.recv().map_err(|e| e.to_string())? {
-            Message::LoadFileRequest(path) => {
+            Message::LoadFileRequest((path, add_hydrogens)) => {
                 let extension = path
                     .extension()
-                    .map(|s| s.to_str().unwrap())
+                    .and_then(|s| s.to_str())
                     .ok_or("Unknown file format")?;
 
                 if !loaders.contains_key(extension) {
                     let obj: Box<dyn FileLoader> = match extension {
                         "sdf" => Box::new(SDFLoader {}),
                         "cif" => Box::new(MMCIFLoader::default()),
+                        "pdb" | "ent" => Box::new(PDBLoader {}),
+                        "xyz" | "extxyz" => Box::new(XYZLoader {}),
                         _ => return Err(String::from("Unknown file type")),
                     };
                     loaders.insert(extension.to_string(), obj);
                 }
 
-                structure = loaders.get_mut(extension).unwrap().parse_file(&path)?;
-                let _ = tx_app.send(Message::LoadSuccess);
+                let loader = loaders.get_mut(extension).ok_or("Unknown file type")?;
+                structures = loader.parse_file(&path)?;
+                for structure in structures.iter_mut() {
+                    if let Some(ccd) = ccd.as_mut() {
+                        if add_hydrogens {
+                            ccd.add_hydrogens(structure);
+                        }
+                        ccd.add_residue_bonds(structure);
+                    }
+                    if structure.infer_bonds {
+                        tessellator.infer_bonds(structure);
+                    }
+                    dssp::assign_secondary_structure(structure);
+                }
+                current = 0;
+                let _ = tx_app.send(Message::LoadSuccess(structures.len()));
+            }
+
+            Message::SelectRecord(index) => {
+                let structure = structures.get(index).ok_or("Record not found")?;
+                let to_vec = |properties: &IndexMap<String, String>| {
+                    properties
+                        .iter()
+                        .map(|(k, v)| (k.clone(), v.clone()))
+                        .collect()
+                };
+                current = index;
+                let _ = tx_app.send(Message::RecordSelected(RecordInfo {
+                    name: structure.name.clone(),
+                    properties: to_vec(&structure.properties),
+                    frames: (0..structure.num_frames())
+                        .map(|i| {
+                            structure
+                                .frames
+                                .get(i)
+                                .map_or(Vec::new(), |f| to_vec(&f.properties))
+                        })
+                        .collect(),
+                    assemblies: structure.assemblies.iter().map(|a| a.to_string()).collect(),
+                    alt_ids: structure
+                        .atoms
+                        .iter()
+                        .filter_map(|a| a.alt_id)
+                        .collect::<BTreeSet<char>>()
+                        .into_iter()
+                        .collect(),
+                    diagnostics: structure
+                        .diagnostics
+                        .iter()
+                        .map(|(message, count)| match count {
+                            1 => message.to_string(),
+                            n => format!("{message} (×{n})"),
+                        })
+                        .collect(),
+                }));
             }
 
             Message::TessRequest((view, front)) => {
-                let structure = tessellator.tessellate(&structure, front, &view);
-                let _ = tx_app.send(Message::TessResponse(structure));
+                let structure = structures.get(current).ok_or("No file loaded")?;
+                let output = tessellator.tessellate(structure, front, &view);
+                let _ = tx_app.send(Message::TessResponse(output));
             }
 
             _ => {}
@@ -91,9 +160,22 @@ impl App {
             ui_state: UIState {
                 file_path: String::from("/home/aabiji/dev/chemview/data/mmcif/28VP.cif"),
                 path_changed: false,
+                add_hydrogens: false,
                 error_message: None,
-                view_type: RenderStyle::Wireframe,
+                view: ViewOptions {
+                    style: RenderStyle::Wireframe,
+                    color_scheme: ColorScheme::Element,
+                    show_interactions: true,
+                    frame: 0,
+                    all_frames: false,
+                    assembly: None,
+                    altloc: AltLocPolicy::HighestOccupancy,
+                },
                 view_changed: false,
+                record_index: 0,
+                record_count: 0,
+                record_changed: false,
+                record: RecordInfo::default(),
                 fps: 0.0,
             },
             renderer: None,
@@ -107,25 +189,49 @@ impl App {
         // Dispatch requests to the loading thread
         if self.ui_state.path_changed {
             let path = PathBuf::from(&self.ui_state.file_path);
-            let _ = self.tx_loader.send(Message::LoadFileRequest(path));
+            let _ = self.tx_loader.send(Message::LoadFileRequest((
+                path,
+                self.ui_state.add_hydrogens,
+            )));
             self.ui_state.path_changed = false;
         }
 
+        if self.ui_state.record_changed {
+            let _ = self
+                .tx_loader
+                .send(Message::SelectRecord(self.ui_state.record_index));
+            self.ui_state.record_changed = false;
+        }
+
         if self.ui_state.view_changed {
             let front = self.renderer.as_mut().unwrap().controller.front();
             let _ = self
                 .tx_loader
-                .send(Message::TessRequest((self.ui_state.view_type, front)));
+                .send(Message::TessRequest((self.ui_state.view, front)));
             self.ui_state.view_changed = false;
         }
 
         // Listen for responses
         if let Ok(msg) = self.rx_app.try_recv() {
             match msg {
-                Message::LoadSuccess => self.ui_state.view_changed = true,
+                Message::LoadSuccess(count) => {
+                    self.ui_state.record_count = count;
+                    self.ui_state.record_index = 0;
+                    self.ui_state.record_changed = true;
+                }
+                Message::RecordSelected(record) => {
+                    self.ui_state.record = record;
+                    self.ui_state.view.frame = 0;
+                    self.ui_state.view.all_frames = false;
+                    self.ui_state.view.assembly = None;
+                    self.ui_state.view.altloc = AltLocPolicy::HighestOccupancy;
+                    self.ui_state.view_changed = true;
+                }
                 Message::TessResponse(output) => {
-                    self.renderer.as_mut().unwrap().set_mesh_data(&output);
-                    self.ui_state.error_message = None;
+                    let left_out = self.renderer.as_mut().unwrap().set_mesh_data(&output);
+                    self.ui_state.error_message = (left_out > 0).then(|| {
+                        format!("Only {MAX_COPIES} copies can be shown, {left_out} were left out")
+                    });
                 }
                 Message::ErrResponse(e) => self.ui_state.error_message = Some(e),
                 _ => {}
@@ -0,0 +1,227 @@
+use glam::Vec3;
+use std::collections::{HashMap, HashSet};
+
+use crate::element::Element;
+use crate::tessellate::{Bond, BondType, Structure};
+
+// Two atoms are bonded when they're closer than the sum of their covalent radii, plus some tolerance
+const TOLERANCE: f32 = 0.45;
+// Atoms that are closer than this are most likely an error in the file
+const MIN_DISTANCE: f32 = 0.4;
+
+// Buckets atoms into cubic cells so that neighbours can be found without
+// comparing every pair of atoms. As long as the cells are as wide as the longest
+// possible bond, bonded atoms are always in the same or in adjacent cells.
+pub struct SpatialGrid {
+    cell_size: f32,
+    cells: HashMap<(i32, i32, i32), Vec<usize>>,
+}
+
+impl SpatialGrid {
+    pub fn new(points: impl Iterator<Item = (usize, Vec3)>, cell_size: f32) -> Self {
+        let mut grid = SpatialGrid {
+            cell_size,
+            cells: HashMap::new(),
+        };
+        for (index, position) in points {
+            let cell = grid.cell(position);
+            grid.cells.entry(cell).or_default().push(index);
+        }
+        grid
+    }
+
+    fn cell(&self, position: Vec3) -> (i32, i32, i32) {
+        let p = (position / self.cell_size).floor();
+        (p.x as i32, p.y as i32, p.z as i32)
+    }
+
+    // Indexes of the points in the cell containing `position` and the 26 cells around it
+    pub fn neighbours(&self, position: Vec3) -> impl Iterator<Item = usize> + '_ {
+        let (x, y, z) = self.cell(position);
+        (-1..=1)
+            .flat_map(move |dx| (-1..=1).flat_map(move |dy| (-1..=1).map(move |dz| (dx, dy, dz))))
+            .filter_map(move |(dx, dy, dz)| self.cells.get(&(x + dx, y + dy, z + dz)))
+            .flatten()
+            .copied()
+    }
+}
+
+// Add single bonds between close atoms in each frame of the structure. Explicit bonds from
+// the file are kept. Within a residue, only pairs where at least one of the atoms has no
+// explicit bonds are considered, so partial connectivity (like CONECT records that only
+// cover ligands) gets completed without doubling up existing bonds. Links between residues
+// are usually left out of the file, so those are looked for, except from atoms that the file
+// already links to another residue, like a metal with its coordination bonds.
+pub fn infer_bonds(structure: &mut Structure, covalent_radius: impl Fn(Element) -> Option<f32>) {
+    let mut has_bonds = vec![false; structure.atoms.len()];
+    let mut bonded: HashSet<(usize, usize)> = HashSet::new();
+    for bond in &structure.bonds {
+        if !matches!(bond.bond_type, BondType::HBond) {
+            has_bonds[bond.src] = true;
+            has_bonds[bond.dst] = true;
+            bonded.insert((bond.src.min(bond.dst), bond.src.max(bond.dst)));
+        }
+    }
+
+    let hierarchy = structure.hierarchy();
+    let mut residue_of = vec![0; structure.atoms.len()];
+    for (index, residue) in hierarchy.residues.iter().enumerate() {
+        residue_of[residue.atoms.clone()].fill(index);
+    }
+
+    let mut has_links = vec![false; structure.atoms.len()];
+    for bond in &structure.bonds {
+        if residue_of[bond.src] != residue_of[bond.dst]
+            && !matches!(bond.bond_type, BondType::HBond)
+        {
+            has_links[bond.src] = true;
+            has_links[bond.dst] = true;
+        }
+    }
+
+    let radii: Vec<Option<f32>> = structure
+        .atoms
+        .iter()
+        .map(|atom| covalent_radius(atom.element))
+        .collect();
+    let max_radius = radii.iter().flatten().copied().fold(0.0, f32::max);
+    let cell_size = (max_radius * 2.0 + TOLERANCE).max(1.0);
+
+    let mut bonds: Vec<Bond> = Vec::new();
+    for model in &hierarchy.models {
+        let atoms = model.atoms.clone();
+        let grid = SpatialGrid::new(
+            atoms.clone().map(|i| (i, structure.atoms[i].position)),
+            cell_size,
+        );
+
+        for i in atoms {
+            let Some(radius_i) = radii[i] else {
+                continue;
+            };
+            let position = structure.atoms[i].position;
+
+            for j in grid.neighbours(position) {
+                // Visit every pair once
+                let same_residue = residue_of[i] == residue_of[j];
+                if j <= i
+                    || (same_residue && has_bonds[i] && has_bonds[j])
+                    || (!same_residue && (has_links[i] || has_links[j]))
+                {
+                    continue;
+                }
+                let Some(radius_j) = radii[j] else {
+                    continue;
+                };
+
+                // Alternate locations of the same atom are close, but never bonded
+                if !structure.atoms[i].same_conformer(&structure.atoms[j]) {
+                    continue;
+                }
+
+                let distance = position.distance(structure.atoms[j].position);
+                if distance > MIN_DISTANCE
+                    && distance < radius_i + radius_j + TOLERANCE
+                    && !bonded.contains(&(i, j))
+                {
+                    bonds.push(Bond {
+                        src: i,
+                        dst: j,
+                        bond_type: BondType::Single,
+                    });
+                }
+            }
+        }
+    }
+
+    structure.bonds.append(&mut bonds);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hierarchy::SeqId;
+    use crate::tessellate::Atom;
+
+    // Every atom is a carbon, with its position and residue number
+    fn structure(atoms: &[(f32, f32, f32, i32)]) -> Structure {
+        let atoms = atoms
+            .iter()
+            .map(|(x, y, z, residue)| Atom {
+                sequence_id: SeqId {
+                    number: *residue,
+                    insertion: None,
+                },
+                element: Element::C,
+                position: Vec3::new(*x, *y, *z),
+                ..Default::default()
+            })
+            .collect();
+        Structure {
+            atoms,
+            ..Default::default()
+        }
+    }
+
+    fn bonds(structure: &Structure) -> Vec<(usize, usize, String)> {
+        let bonds = structure.bonds.iter();
+        bonds
+            .map(|b| (b.src, b.dst, format!("{:?}", b.bond_type)))
+            .collect()
+    }
+
+    fn infer(structure: &mut Structure) {
+        infer_bonds(structure, |element| (element == Element::C).then_some(0.76));
+    }
+
+    // The cells are 1.97 Å wide, so each pair is in two different cells
+    #[test]
+    fn bonds_atoms_in_neighbouring_cells() {
+        let mut s = structure(&[(1.2, 0.0, 0.0, 1), (2.5, 0.0, 0.0, 1)]);
+        infer(&mut s);
+        assert_eq!(bonds(&s), [(0, 1, String::from("Single"))]);
+
+        let mut s = structure(&[(-0.7, -0.7, 0.0, 1), (0.3, 0.3, 0.0, 1)]);
+        infer(&mut s);
+        assert_eq!(bonds(&s), [(0, 1, String::from("Single"))]);
+    }
+
+    #[test]
+    fn skips_overlapping_atoms() {
+        let mut s = structure(&[(0.0, 0.0, 0.0, 1), (0.3, 0.0, 0.0, 1)]);
+        infer(&mut s);
+        assert!(s.bonds.is_empty());
+    }
+
+    // Residues that the file bonds completely keep their bonds as they are, and atoms it
+    // links to another residue don't get links inferred
+    #[test]
+    fn keeps_explicit_bonds() {
+        let mut s = structure(&[
+            (0.0, 0.0, 0.0, 1),
+            (1.3, 0.0, 0.0, 1),
+            (2.6, 0.0, 0.0, 2),
+            (2.6, 1.3, 0.0, 2),
+        ]);
+        s.bonds = vec![
+            Bond {
+                src: 0,
+                dst: 1,
+                bond_type: BondType::Double,
+            },
+            Bond {
+                src: 1,
+                dst: 3,
+                bond_type: BondType::Single,
+            },
+            Bond {
+                src: 2,
+                dst: 3,
+                bond_type: BondType::Aromatic,
+            },
+        ];
+        let before = bonds(&s);
+        infer(&mut s);
+        assert_eq!(bonds(&s), before);
+    }
+}
@@ -0,0 +1,200 @@
+use glam::Vec3;
+use std::f32::consts::PI;
+
+use crate::shape::{Shape, Vertex};
+
+// The cartoon of a protein is a spline through the CA atoms of each chain, swept into a
+// wide ribbon along helices, a flat arrow along strands and a thin tube everywhere else.
+
+// Points around the outline of the cross section
+const SIDES: usize = 12;
+// Vertices for the cartoon of a whole structure, past which it gets less detailed.
+// Past the least detail, the renderer splits it over more than one buffer.
+const MAX_VERTICES: usize = 4_000_000;
+// CA atoms further apart than this are on either side of a gap in the model
+pub const MAX_CA_DISTANCE: f32 = 4.2;
+
+const COIL_RADIUS: f32 = 0.25;
+const ARROW_WIDTH: f32 = 1.5; // half width at the base of the arrow head
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Profile {
+    Helix,
+    NarrowHelix, // a 3-10 helix, which is tighter than an alpha helix
+    Strand,
+    Coil,
+}
+
+impl Profile {
+    // Half width, half thickness and how square the cross section is (2 is an ellipse)
+    fn shape(&self) -> (f32, f32, f32) {
+        match self {
+            Profile::Helix => (1.2, 0.2, 2.0),
+            Profile::NarrowHelix => (0.8, 0.2, 2.0),
+            Profile::Strand => (0.9, 0.25, 6.0),
+            Profile::Coil => (COIL_RADIUS, COIL_RADIUS, 2.0),
+        }
+    }
+}
+
+// A residue that the spline goes through
+pub struct Guide {
+    pub position: Vec3, // of the CA atom
+    pub side: Vec3,     // the C=O bond, which the ribbon is flat along. Zero when there's no O.
+    pub profile: Profile,
+    pub color: Vec3,
+}
+
+// Points of the spline between each pair of residues, so that the cartoon of a large
+// structure doesn't take up too much memory
+pub fn subdivisions(num_residues: usize) -> usize {
+    (MAX_VERTICES / (num_residues * SIDES).max(1)).clamp(2, 8)
+}
+
+// Catmull-Rom spline through p1 and p2, and its tangent
+fn spline(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> (Vec3, Vec3) {
+    let (t2, t3) = (t * t, t * t * t);
+    let position = 0.5
+        * (2.0 * p1
+            + (p2 - p0) * t
+            + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+            + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3);
+    let tangent = 0.5
+        * ((p2 - p0)
+            + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * 2.0 * t
+            + (3.0 * p1 - p0 - 3.0 * p2 + p3) * 3.0 * t2);
+    (position, tangent)
+}
+
+// Points around a superellipse with the given half width and thickness, and their normals.
+// The higher the exponent, the closer it gets to a rectangle.
+fn cross_section(width: f32, thickness: f32, exponent: f32) -> [(f32, f32, f32, f32); SIDES] {
+    std::array::from_fn(|j| {
+        let angle = 2.0 * PI * j as f32 / SIDES as f32;
+        let (sin, cos) = angle.sin_cos();
+        let curve = |c: f32, power: f32| c.signum() * c.abs().powf(power);
+        let (x, y) = (
+            width * curve(cos, 2.0 / exponent),
+            thickness * curve(sin, 2.0 / exponent),
+        );
+        let (nx, ny) = (
+            curve(cos, 2.0 - 2.0 / exponent) / width,
+            curve(sin, 2.0 - 2.0 / exponent) / thickness,
+        );
+        (x, y, nx, ny)
+    })
+}
+
+// Sweep the cross sections along a spline through the residues of a chain without gaps
+pub fn sweep(guides: &[Guide], subdivisions: usize) -> Option<Shape> {
+    let n = guides.len();
+    if n < 2 {
+        return None;
+    }
+
+    // The CA atoms of a strand zigzag, so they're smoothed out to get a flat arrow
+    let is_strand = |i: usize| guides[i].profile == Profile::Strand;
+    let points: Vec<Vec3> = (0..n)
+        .map(
+            |i| match i > 0 && i + 1 < n && is_strand(i - 1) && is_strand(i) && is_strand(i + 1) {
+                true => {
+                    (guides[i - 1].position + 2.0 * guides[i].position + guides[i + 1].position)
+                        / 4.0
+                }
+                false => guides[i].position,
+            },
+        )
+        .collect();
+    let point = |i: isize| points[i.clamp(0, n as isize - 1) as usize];
+
+    // Flip the C=O directions that point the other way from the previous one, so
+    // that the ribbon doesn't twist around itself
+    let mut sides: Vec<Vec3> = Vec::with_capacity(n);
+    for (i, guide) in guides.iter().enumerate() {
+        let tangent = (point(i as isize + 1) - point(i as isize - 1)).normalize_or_zero();
+        let side = guide.side - tangent * tangent.dot(guide.side);
+        let side = match side.length() > 0.01 {
+            true => side.normalize(),
+            false => sides
+                .last()
+                .copied()
+                .unwrap_or_else(|| tangent.any_orthonormal_vector()),
+        };
+        let flip = sides.last().is_some_and(|prev| prev.dot(side) < 0.0);
+        sides.push(if flip { -side } else { side });
+    }
+
+    let mut vertices: Vec<Vertex> = Vec::new();
+    let mut indices: Vec<u32> = Vec::new();
+    let mut ends: Vec<(Vec3, Vec3, usize)> = Vec::new(); // center, tangent and first vertex of each end
+
+    let num_samples = (n - 1) * subdivisions + 1;
+    for k in 0..num_samples {
+        let (i, t) = (
+            (k / subdivisions).min(n - 2),
+            k as f32 / subdivisions as f32,
+        );
+        let t = t - i as f32;
+        let i = i as isize;
+        let (center, tangent) = spline(point(i - 1), point(i), point(i + 1), point(i + 2), t);
+        let tangent = tangent.normalize_or_zero();
+        let side = sides[i as usize].lerp(sides[i as usize + 1], t);
+        let side = (side - tangent * tangent.dot(side)).normalize_or_zero();
+        let up = tangent.cross(side);
+
+        // Each residue covers the half of the spline on either side of it
+        let (r, u) = match t < 0.5 {
+            true => (i as usize, t),
+            false => (i as usize + 1, t - 1.0),
+        };
+        let guide = &guides[r];
+        let (mut width, thickness, exponent) = guide.profile.shape();
+        let is_last = r + 1 == n || guides[r + 1].profile != Profile::Strand;
+        if guide.profile == Profile::Strand && is_last {
+            width = ARROW_WIDTH + (COIL_RADIUS - ARROW_WIDTH) * (u + 0.5);
+        }
+
+        if k == 0 || k == num_samples - 1 {
+            ends.push((center, tangent, vertices.len()));
+        }
+        for (x, y, nx, ny) in cross_section(width, thickness, exponent) {
+            let normal = (side * nx + up * ny).normalize_or_zero();
+            vertices.push(Vertex::colored(
+                center + side * x + up * y,
+                normal,
+                guide.color,
+            ));
+        }
+
+        if k > 0 {
+            let ring = (vertices.len() - 2 * SIDES) as u32;
+            for j in 0..SIDES as u32 {
+                let next = (j + 1) % SIDES as u32;
+                let (a, b) = (ring + j, ring + next);
+                let (c, d) = (a + SIDES as u32, b + SIDES as u32);
+                indices.extend([a, b, c, c, b, d]);
+            }
+        }
+    }
+
+    // Close off both ends
+    for (end, (center, tangent, first)) in ends.into_iter().enumerate() {
+        let normal = if end == 0 { -tangent } else { tangent };
+        let start = vertices.len() as u32;
+        vertices.push(Vertex::colored(
+            center,
+            normal,
+            Vec3::from_slice(&vertices[first].color[..3]),
+        ));
+        for j in 0..SIDES {
+            let position = Vec3::from_slice(&vertices[first + j].position[..3]);
+            let color = Vec3::from_slice(&vertices[first + j].color[..3]);
+            vertices.push(Vertex::colored(position, normal, color));
+        }
+        for j in 0..SIDES as u32 {
+            indices.extend([start, start + 1 + j, start + 1 + (j + 1) % SIDES as u32]);
+        }
+    }
+
+    Some(Shape::Mesh { vertices, indices })
+}
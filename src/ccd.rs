@@ -0,0 +1,290 @@
+use glam::{Mat3, Vec3};
+use itertools::Itertools;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+
+use crate::element::Element;
+use crate::hierarchy::Label;
+use crate::loader::{MMCIFLoader, chem_comp_bond_type};
+use crate::tessellate::{Atom, Bond, BondType, Structure};
+
+pub struct ComponentAtom {
+    pub atom_id: Label,
+    pub element: Element,
+    pub ideal_position: Option<Vec3>,
+    pub leaving: bool, // removed when the component is linked to another, like OXT in a peptide bond
+}
+
+pub struct ComponentBond {
+    pub atom_id_1: Label,
+    pub atom_id_2: Label,
+    pub bond_type: BondType,
+}
+
+// Reference definition of a residue or ligand
+#[derive(Default)]
+pub struct Component {
+    pub atoms: Vec<ComponentAtom>,
+    pub bonds: Vec<ComponentBond>,
+}
+
+impl Component {
+    fn atom(&self, atom_id: Label) -> Option<&ComponentAtom> {
+        self.atoms.iter().find(|a| a.atom_id == atom_id)
+    }
+
+    fn neighbours(&self, atom_id: Label) -> impl Iterator<Item = Label> + '_ {
+        self.bonds.iter().filter_map(move |b| {
+            if b.atom_id_1 == atom_id {
+                Some(b.atom_id_2)
+            } else if b.atom_id_2 == atom_id {
+                Some(b.atom_id_1)
+            } else {
+                None
+            }
+        })
+    }
+
+    // Hydrogens that stay on the component when it's linked, along with the atom they're attached to
+    pub fn hydrogens(&self) -> impl Iterator<Item = (&ComponentAtom, Label)> {
+        self.atoms
+            .iter()
+            .filter(|a| a.element == Element::H && !a.leaving)
+            .filter_map(|a| Some((a, self.neighbours(a.atom_id).next()?)))
+    }
+}
+
+// Index into the Chemical Component Dictionary (https://www.wwpdb.org/data/ccd).
+// The file has one data block per component and is hundreds of megabytes, so only
+// the block offsets are scanned up front, and components are parsed on first use.
+pub struct ComponentDictionary {
+    file: MMCIFLoader,
+    components: HashMap<String, Option<Component>>, // None when the component isn't in the file
+}
+
+impl ComponentDictionary {
+    pub fn open(path: &Path) -> Result<ComponentDictionary, String> {
+        let mut file = MMCIFLoader::default();
+        file.open_file(path)?;
+        Ok(ComponentDictionary {
+            file,
+            components: HashMap::new(),
+        })
+    }
+
+    pub fn component(&mut self, comp_id: &str) -> Option<&Component> {
+        if !self.components.contains_key(comp_id) {
+            let component = self.parse_component(comp_id).ok();
+            self.components.insert(comp_id.to_string(), component);
+        }
+        self.components.get(comp_id)?.as_ref()
+    }
+
+    fn parse_component(&mut self, comp_id: &str) -> Result<Component, String> {
+        self.file.parse_block(Some(comp_id))?;
+        let mut component = Component::default();
+
+        let t = self.file.get_table(Some(comp_id), "chem_comp_atom")?;
+        for i in 0..t.num_rows {
+            let coordinate = |axis: &str| {
+                t.optional::<f32>(&format!("pdbx_model_Cartn_{axis}_ideal"), i)
+                    .or_else(|| t.optional::<f32>(&format!("model_Cartn_{axis}"), i))
+            };
+            component.atoms.push(ComponentAtom {
+                atom_id: t.label("atom_id", i)?,
+                element: Element::from_symbol(t.string("type_symbol", i)?),
+                ideal_position: match (coordinate("x"), coordinate("y"), coordinate("z")) {
+                    (Some(x), Some(y), Some(z)) => Some(Vec3::new(x, y, z)),
+                    _ => None,
+                },
+                leaving: t.optional::<String>("pdbx_leaving_atom_flag", i).as_deref() == Some("Y"),
+            });
+        }
+
+        // Single atom components (ions) have no bonds
+        if let Ok(t) = self.file.get_table(Some(comp_id), "chem_comp_bond") {
+            for i in 0..t.num_rows {
+                component.bonds.push(ComponentBond {
+                    atom_id_1: t.label("atom_id_1", i)?,
+                    atom_id_2: t.label("atom_id_2", i)?,
+                    bond_type: chem_comp_bond_type(&t, i)?,
+                });
+            }
+        }
+
+        Ok(component)
+    }
+
+    // Add the bonds within each residue that the file leaves out. Most deposited
+    // structures only list the connectivity of ligands, if at all.
+    pub fn add_residue_bonds(&mut self, structure: &mut Structure) {
+        let mut bonded: HashSet<(usize, usize)> = structure
+            .bonds
+            .iter()
+            .map(|b| (b.src.min(b.dst), b.src.max(b.dst)))
+            .collect();
+        let mut bonds: Vec<Bond> = Vec::new();
+
+        for residue in &structure.hierarchy().residues {
+            let Some(component) = self.component(residue.name) else {
+                continue;
+            };
+            let mut atoms: HashMap<Label, Vec<usize>> = HashMap::new();
+            for i in residue.atoms.clone() {
+                atoms.entry(structure.atoms[i].atom_id).or_default().push(i);
+            }
+
+            for bond in &component.bonds {
+                let srcs = atoms.get(&bond.atom_id_1);
+                let dsts = atoms.get(&bond.atom_id_2);
+                let (Some(srcs), Some(dsts)) = (srcs, dsts) else {
+                    continue;
+                };
+                for (&src, &dst) in srcs.iter().cartesian_product(dsts) {
+                    if structure.atoms[src].same_conformer(&structure.atoms[dst])
+                        && bonded.insert((src.min(dst), src.max(dst)))
+                    {
+                        bonds.push(Bond {
+                            src,
+                            dst,
+                            bond_type: bond.bond_type,
+                        });
+                    }
+                }
+            }
+        }
+
+        structure.bonds.append(&mut bonds);
+    }
+
+    // Add the hydrogens that the file leaves out, which is most X-ray structures.
+    // The ideal coordinates of each hydrogen are fitted onto the heavy atoms around the
+    // atom it's attached to, so it ends up with the right bond length and geometry.
+    pub fn add_hydrogens(&mut self, structure: &mut Structure) {
+        let mut attached: Vec<(usize, Atom, BondType)> = Vec::new();
+
+        let hierarchy = structure.hierarchy();
+        for residue in &hierarchy.residues {
+            let first = &structure.atoms[residue.atoms.start];
+            let Some(component) = self.component(&first.component_name) else {
+                continue;
+            };
+
+            // Each alternate location of the residue gets its own hydrogens. Atoms
+            // without an alternate location are shared by all of them.
+            let alt_ids: BTreeSet<char> = residue
+                .atoms
+                .clone()
+                .filter_map(|i| structure.atoms[i].alt_id)
+                .collect();
+            let conformers: Vec<Option<char>> = match alt_ids.is_empty() {
+                true => vec![None],
+                false => alt_ids.into_iter().map(Some).collect(),
+            };
+
+            for (k, alt_id) in conformers.into_iter().enumerate() {
+                let atoms: HashMap<Label, usize> = residue
+                    .atoms
+                    .clone()
+                    .filter(|i| {
+                        let atom_alt = structure.atoms[*i].alt_id;
+                        atom_alt.is_none() || atom_alt == alt_id
+                    })
+                    .map(|i| (structure.atoms[i].atom_id, i))
+                    .collect();
+
+                for (hydrogen, parent_id) in component.hydrogens() {
+                    let Some(&parent) = atoms.get(&parent_id) else {
+                        continue;
+                    };
+                    let parent_atom = &structure.atoms[parent];
+                    // Shared atoms already got their hydrogens with the first conformer
+                    if atoms.contains_key(&hydrogen.atom_id)
+                        || (k > 0 && parent_atom.alt_id.is_none())
+                    {
+                        continue;
+                    }
+                    let Some(position) =
+                        place_atom(component, &atoms, structure, hydrogen, parent_id)
+                    else {
+                        continue;
+                    };
+
+                    attached.push((
+                        parent,
+                        Atom {
+                            chain_id: first.chain_id,
+                            sequence_id: first.sequence_id,
+                            component_name: first.component_name,
+                            atom_id: hydrogen.atom_id,
+                            element: hydrogen.element,
+                            is_ligand: first.is_ligand,
+                            position,
+                            alt_id: parent_atom.alt_id,
+                            occupancy: parent_atom.occupancy,
+                            b_factor: parent_atom.b_factor,
+                            ..Default::default()
+                        },
+                        BondType::Single,
+                    ));
+                }
+            }
+        }
+
+        structure.insert_attached_atoms(attached);
+    }
+}
+
+// Orthonormal basis from the direction to `a` and the part of the direction to `b` that's
+// perpendicular to it. None when the three points are (close to) collinear.
+fn local_frame(origin: Vec3, a: Vec3, b: Vec3) -> Option<Mat3> {
+    let x = (a - origin).normalize();
+    let to_b = b - origin;
+    let y = to_b - x * x.dot(to_b);
+    if !x.is_finite() || y.length() < 0.1 {
+        return None;
+    }
+    let y = y.normalize();
+    Some(Mat3::from_cols(x, y, x.cross(y)))
+}
+
+// Position of a missing atom in the structure. Its ideal position relative to the atom it's
+// attached to is carried over to the structure, using the nearest heavy atoms that are in
+// both to line up the two orientations.
+fn place_atom(
+    component: &Component,
+    atoms: &HashMap<Label, usize>,
+    structure: &Structure,
+    missing: &ComponentAtom,
+    parent_id: Label,
+) -> Option<Vec3> {
+    let ideal = |id: Label| component.atom(id)?.ideal_position;
+    let actual = |id: Label| Some(structure.atoms[*atoms.get(&id)?].position);
+
+    // Breadth first search for heavy atoms near the parent
+    let mut nearby: Vec<Label> = vec![parent_id];
+    let mut k = 0;
+    while k < nearby.len() && nearby.len() < 6 {
+        for id in component.neighbours(nearby[k]) {
+            let is_heavy = component.atom(id).is_some_and(|a| a.element != Element::H);
+            if is_heavy && atoms.contains_key(&id) && !nearby.contains(&id) {
+                nearby.push(id);
+            }
+        }
+        k += 1;
+    }
+
+    let parent_ideal = ideal(parent_id)?;
+    let parent_actual = actual(parent_id)?;
+    for (i, a) in nearby.iter().enumerate().skip(1) {
+        for b in &nearby[i + 1..] {
+            let ideal_frame = local_frame(parent_ideal, ideal(*a)?, ideal(*b)?);
+            let actual_frame = local_frame(parent_actual, actual(*a)?, actual(*b)?);
+            if let (Some(from), Some(to)) = (ideal_frame, actual_frame) {
+                let offset = missing.ideal_position? - parent_ideal;
+                return Some(parent_actual + to * from.transpose() * offset);
+            }
+        }
+    }
+    None
+}
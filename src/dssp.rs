@@ -0,0 +1,461 @@
+use glam::Vec3;
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::bonding::SpatialGrid;
+use crate::hierarchy::{Hierarchy, Model};
+use crate::tessellate::{SecondaryStructure, SecondaryType, Structure};
+
+// Secondary structure assignment from the hydrogen bonds of the protein backbone, following
+// DSSP (Kabsch & Sander, https://doi.org/10.1002/bip.360221211). Files without HELIX/SHEET
+// records or struct_conf tables, like predicted models and simulation frames, get their
+// secondary structure from this.
+
+// Electrostatic energy of a hydrogen bond, in kcal/mol: partial charges of 0.42e on C=O
+// and 0.20e on N-H, times 332 to convert the units
+const COUPLING: f32 = 0.42 * 0.20 * 332.0;
+const MIN_ENERGY: f32 = -9.9;
+// Pairs with less energy than this are hydrogen bonded
+const MAX_HBOND_ENERGY: f32 = -0.5;
+// Residues with CA atoms further apart than this can't be hydrogen bonded
+const MAX_CA_DISTANCE: f32 = 9.0;
+// A C-N distance longer than this is a gap in the chain, not a peptide bond
+const MAX_PEPTIDE_BOND: f32 = 2.5;
+// Angle between CA(i-2) -> CA(i) and CA(i) -> CA(i+2), in degrees
+const MIN_BEND_ANGLE: f32 = 70.0;
+
+// One letter codes that DSSP uses for each residue
+const LOOP: u8 = b' ';
+const ALPHA_HELIX: u8 = b'H';
+const HELIX_310: u8 = b'G';
+const PI_HELIX: u8 = b'I';
+const STRAND: u8 = b'E';
+const BRIDGE: u8 = b'B'; // a strand of a single residue
+const TURN: u8 = b'T';
+const BEND: u8 = b'S';
+
+struct Backbone {
+    n: Vec3,
+    ca: Vec3,
+    c: Vec3,
+    o: Vec3,
+    h: Option<Vec3>, // none for proline and the first residue after a gap
+    atoms: Range<usize>,
+    segment: usize, // residues of a segment are linked by peptide bonds, without gaps
+}
+
+// Consecutive bridges between two strands
+struct Ladder {
+    parallel: bool,
+    i: (usize, usize), // first and last residue of the first strand
+    j: (usize, usize), // lowest and highest residue of the second strand
+    last_j: usize,
+    bridges: usize,
+    linked: bool, // continues another ladder over a bulge
+}
+
+impl Ladder {
+    fn overlaps(&self, other: &Ladder) -> bool {
+        let intersect = |a: (usize, usize), b: (usize, usize)| a.0 <= b.1 && b.0 <= a.1;
+        [self.i, self.j]
+            .iter()
+            .cartesian_product([other.i, other.j])
+            .any(|(a, b)| intersect(*a, b))
+    }
+}
+
+// Secondary structure from the file takes precedence, so this only fills it in when
+// there is none
+pub fn assign_secondary_structure(structure: &mut Structure) {
+    if !structure.secondary.is_empty() {
+        return;
+    }
+    let hierarchy = structure.hierarchy();
+    let mut secondary: Vec<SecondaryStructure> = Vec::new();
+    for model in &hierarchy.models {
+        let residues = backbone(&hierarchy, model);
+        secondary.append(&mut assign_model(&residues));
+    }
+    structure.secondary = secondary;
+}
+
+// Residues with a complete backbone, in chain order. Only the first of alternate residues
+// and alternate locations is used.
+fn backbone(hierarchy: &Hierarchy, model: &Model) -> Vec<Backbone> {
+    let atoms = hierarchy.atoms;
+    let mut residues: Vec<Backbone> = Vec::new();
+    let mut segment = 0;
+
+    for chain in hierarchy.chains(model) {
+        segment += 1;
+        for position in hierarchy
+            .residues(chain)
+            .chunk_by(|a, b| a.seq_id == b.seq_id)
+        {
+            let residue = &position[0];
+            let find = |atom_id: &str| {
+                let mut indexes = residue.atoms.clone();
+                let index = indexes.find(|i| atoms[*i].atom_id == atom_id);
+                index.map(|i| atoms[i].position)
+            };
+            let (Some(n), Some(ca), Some(c), Some(o)) =
+                (find("N"), find("CA"), find("C"), find("O"))
+            else {
+                segment += 1;
+                continue;
+            };
+
+            let prev = residues
+                .last()
+                .filter(|p| p.segment == segment && p.c.distance(n) < MAX_PEPTIDE_BOND);
+            // Most files leave out the hydrogens, so the one on N is placed opposite
+            // the C=O of the previous residue, 1 Å away
+            let h = prev
+                .filter(|_| residue.name != "PRO")
+                .map(|p| n + (p.c - p.o).normalize());
+            if prev.is_none() {
+                segment += 1;
+            }
+
+            residues.push(Backbone {
+                n,
+                ca,
+                c,
+                o,
+                h,
+                atoms: residue.atoms.clone(),
+                segment,
+            });
+        }
+    }
+    residues
+}
+
+fn hbond_energy(donor: &Backbone, acceptor: &Backbone) -> f32 {
+    let Some(h) = donor.h else {
+        return 0.0;
+    };
+    let r_on = acceptor.o.distance(donor.n);
+    let r_ch = acceptor.c.distance(h);
+    let r_oh = acceptor.o.distance(h);
+    let r_cn = acceptor.c.distance(donor.n);
+    // Overlapping atoms
+    if r_on.min(r_ch).min(r_oh).min(r_cn) < 0.5 {
+        return MIN_ENERGY;
+    }
+    (COUPLING * (1.0 / r_on + 1.0 / r_ch - 1.0 / r_oh - 1.0 / r_cn)).max(MIN_ENERGY)
+}
+
+fn assign_model(residues: &[Backbone]) -> Vec<SecondaryStructure> {
+    let len = residues.len();
+    let grid = SpatialGrid::new(residues.iter().map(|r| r.ca).enumerate(), MAX_CA_DISTANCE);
+    let close = |i: usize| {
+        grid.neighbours(residues[i].ca)
+            .filter(move |j| residues[i].ca.distance(residues[*j].ca) < MAX_CA_DISTANCE)
+    };
+
+    // The two strongest bonds from the N-H of each residue to the C=O of another
+    let mut acceptors = vec![[(usize::MAX, 0.0f32); 2]; len];
+    for donor in 0..len {
+        for acceptor in close(donor) {
+            // Neighbours are linked by a peptide bond, not a hydrogen bond
+            if acceptor == donor || acceptor + 1 == donor {
+                continue;
+            }
+            let energy = hbond_energy(&residues[donor], &residues[acceptor]);
+            let best = &mut acceptors[donor];
+            if energy < best[0].1 {
+                best[1] = best[0];
+                best[0] = (acceptor, energy);
+            } else if energy < best[1].1 {
+                best[1] = (acceptor, energy);
+            }
+        }
+    }
+
+    // Whether the C=O of residue i is bonded to the N-H of residue j
+    let hbond = |i: usize, j: usize| {
+        acceptors
+            .get(j)
+            .is_some_and(|best| best.iter().any(|&(a, e)| a == i && e < MAX_HBOND_ENERGY))
+    };
+    // Whether residues i to j are linked without a gap
+    let linked = |i: usize, j: usize| {
+        let segment = |k: usize| residues.get(k).map(|r| r.segment);
+        segment(i).is_some() && segment(i) == segment(j)
+    };
+
+    // An n-turn at i is a bond from the C=O of i to the N-H of i + n
+    let turns: Vec<Vec<bool>> = (3..=5)
+        .map(|n| {
+            (0..len)
+                .map(|i| linked(i, i + n) && hbond(i, i + n))
+                .collect()
+        })
+        .collect();
+    let turn = |n: usize, i: usize| turns[n - 3][i];
+
+    let mut ss = vec![LOOP; len];
+    let mut sheet_of: Vec<Option<usize>> = vec![None; len];
+
+    // Bridges between residues i and j, which are in two strands next to each other
+    let mut bridges: Vec<(usize, usize, bool)> = Vec::new();
+    for i in 1..len.saturating_sub(1) {
+        if !linked(i - 1, i + 1) {
+            continue;
+        }
+        for j in close(i).filter(|j| *j > i + 2).sorted() {
+            if !linked(j - 1, j + 1) {
+                continue;
+            }
+            let parallel =
+                (hbond(i - 1, j) && hbond(j, i + 1)) || (hbond(j - 1, i) && hbond(i, j + 1));
+            let antiparallel =
+                (hbond(i, j) && hbond(j, i)) || (hbond(i - 1, j + 1) && hbond(j - 1, i + 1));
+            if parallel || antiparallel {
+                bridges.push((i, j, parallel));
+            }
+        }
+    }
+
+    let mut ladders: Vec<Ladder> = Vec::new();
+    for (i, j, parallel) in bridges {
+        let next = |l: &Ladder| match parallel {
+            true => l.last_j + 1 == j,
+            false => l.last_j == j + 1,
+        };
+        let ladder = ladders
+            .iter_mut()
+            .find(|l| l.parallel == parallel && l.i.1 + 1 == i && next(l));
+        match ladder {
+            Some(ladder) => {
+                ladder.i.1 = i;
+                ladder.j = (ladder.j.0.min(j), ladder.j.1.max(j));
+                ladder.last_j = j;
+                ladder.bridges += 1;
+            }
+            None => ladders.push(Ladder {
+                parallel,
+                i: (i, i),
+                j: (j, j),
+                last_j: j,
+                bridges: 1,
+                linked: false,
+            }),
+        }
+    }
+
+    // Ladders that are interrupted by a bulge, a gap of up to 4 residues on one strand
+    // and 1 on the other, are one ladder
+    let mut bulges: Vec<(usize, usize)> = Vec::new();
+    for (a, b) in (0..ladders.len()).tuple_combinations() {
+        let (first, second) = (&ladders[a], &ladders[b]);
+        if first.parallel != second.parallel || second.i.0 <= first.i.1 {
+            continue;
+        }
+        let gap_i = second.i.0 - first.i.1;
+        let (gap_j, j_linked) = match first.parallel {
+            true => (
+                second.j.0.checked_sub(first.j.1),
+                linked(first.j.1, second.j.0),
+            ),
+            false => (
+                first.j.0.checked_sub(second.j.1),
+                linked(second.j.1, first.j.0),
+            ),
+        };
+        let Some(gap_j) = gap_j else {
+            continue;
+        };
+        let bulge = (gap_i < 6 && gap_j < 3) || (gap_i < 3 && gap_j < 6);
+        if bulge && j_linked && linked(first.i.1, second.i.0) {
+            bulges.push((a, b));
+        }
+    }
+    for &(a, b) in &bulges {
+        ladders[a].linked = true;
+        ladders[b].linked = true;
+    }
+
+    // Ladders that share a strand are in the same sheet
+    let mut sheets: Vec<usize> = (0..ladders.len()).collect();
+    fn root(sheets: &mut [usize], mut k: usize) -> usize {
+        while sheets[k] != k {
+            sheets[k] = sheets[sheets[k]];
+            k = sheets[k];
+        }
+        k
+    }
+    for (a, b) in (0..ladders.len()).tuple_combinations() {
+        if bulges.contains(&(a, b)) || ladders[a].overlaps(&ladders[b]) {
+            let (root_a, root_b) = (root(&mut sheets, a), root(&mut sheets, b));
+            sheets[root_b] = root_a;
+        }
+    }
+
+    let mut mark_strand = |range: (usize, usize), code: u8, sheet: usize| {
+        for k in range.0..=range.1 {
+            if code == STRAND || ss[k] == LOOP {
+                ss[k] = code;
+                sheet_of[k] = Some(sheet);
+            }
+        }
+    };
+    for (k, ladder) in ladders.iter().enumerate() {
+        let sheet = root(&mut sheets, k);
+        let code = match ladder.bridges > 1 || ladder.linked {
+            true => STRAND,
+            false => BRIDGE,
+        };
+        mark_strand(ladder.i, code, sheet);
+        mark_strand(ladder.j, code, sheet);
+    }
+    // The residues of a bulge are part of the strand
+    for &(a, b) in &bulges {
+        let sheet = root(&mut sheets, a);
+        let (first, second) = (&ladders[a], &ladders[b]);
+        mark_strand((first.i.0, second.i.1), STRAND, sheet);
+        let j = (first.j.0.min(second.j.0), first.j.1.max(second.j.1));
+        mark_strand(j, STRAND, sheet);
+    }
+
+    // Two consecutive 4-turns make an alpha helix, which takes priority over strands
+    for i in 1..len {
+        if turn(4, i - 1) && turn(4, i) {
+            ss[i..i + 4].fill(ALPHA_HELIX);
+        }
+    }
+    // 3-10 and pi helices only go where there's nothing else
+    for (n, code) in [(3, HELIX_310), (5, PI_HELIX)] {
+        for i in 1..len {
+            let free = ss[i..len.min(i + n)]
+                .iter()
+                .all(|c| *c == LOOP || *c == code);
+            if turn(n, i - 1) && turn(n, i) && free {
+                ss[i..i + n].fill(code);
+            }
+        }
+    }
+
+    // Turns that aren't part of a helix
+    for n in 3..=5 {
+        for i in 0..len {
+            if turn(n, i) {
+                for code in &mut ss[i + 1..i + n] {
+                    if *code == LOOP {
+                        *code = TURN;
+                    }
+                }
+            }
+        }
+    }
+
+    // Sharp bends of the chain
+    for i in 2..len.saturating_sub(2) {
+        if ss[i] != LOOP || !linked(i - 2, i + 2) {
+            continue;
+        }
+        let before = residues[i].ca - residues[i - 2].ca;
+        let after = residues[i + 2].ca - residues[i].ca;
+        if before.angle_between(after).to_degrees() > MIN_BEND_ANGLE {
+            ss[i] = BEND;
+        }
+    }
+
+    // Runs of residues with the same code
+    let mut sheet_ids: HashMap<usize, String> = HashMap::new();
+    let mut secondary: Vec<SecondaryStructure> = Vec::new();
+    let key = |k: usize| (ss[k], residues[k].segment, sheet_of[k]);
+    let indexes: Vec<usize> = (0..len).collect();
+    for run in indexes.chunk_by(|a, b| key(*a) == key(*b)) {
+        let (code, _, sheet) = key(run[0]);
+        let struct_type = match code {
+            ALPHA_HELIX => SecondaryType::AlphaHelix,
+            HELIX_310 => SecondaryType::Helix310,
+            PI_HELIX => SecondaryType::PiHelix,
+            STRAND => SecondaryType::BetaSheet,
+            TURN => SecondaryType::Turn,
+            BEND => SecondaryType::Bend,
+            _ => continue,
+        };
+        let sheet_id = sheet.filter(|_| code == STRAND).map(|sheet| {
+            let next_id = sheet_ids.len() + 1;
+            sheet_ids
+                .entry(sheet)
+                .or_insert(next_id.to_string())
+                .clone()
+        });
+        secondary.push(SecondaryStructure {
+            struct_type,
+            start: residues[run[0]].atoms.start,
+            end: residues[run[run.len() - 1]].atoms.end - 1,
+            sheet_id,
+        });
+    }
+    secondary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{FileLoader, PDBLoader};
+    use std::path::Path;
+
+    // A 12 residue helix in chain A, and a parallel sheet of two 8 residue strands in
+    // chains B and C, with the given records in front of the atoms
+    fn load_fixture(name: &str, records: &str) -> Structure {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("data/dssp_helix_sheet.pdb");
+        let text = records.to_string() + &std::fs::read_to_string(fixture).unwrap();
+        let path = std::env::temp_dir().join(format!("chemview_test_{name}.pdb"));
+        std::fs::write(&path, text).unwrap();
+        let mut structures = PDBLoader {}.parse_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        structures.remove(0)
+    }
+
+    // Type, first residue and last residue of each range
+    fn ranges(structure: &Structure) -> Vec<String> {
+        let residue = |i: usize| {
+            let atom = &structure.atoms[i];
+            format!("{}{}", atom.chain_id, atom.sequence_id.number)
+        };
+        let secondary = structure.secondary.iter();
+        secondary
+            .map(|s| {
+                format!(
+                    "{:?} {}-{}",
+                    s.struct_type,
+                    residue(s.start),
+                    residue(s.end)
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn assigns_helix_and_sheet() {
+        let mut structure = load_fixture("dssp", "");
+        assign_secondary_structure(&mut structure);
+        assert_eq!(
+            ranges(&structure),
+            ["AlphaHelix A2-A11", "BetaSheet B2-B7", "BetaSheet C2-C7"]
+        );
+
+        // Ranges cover whole residues, and both strands are in the same sheet
+        let last = &structure.atoms[structure.secondary[0].end];
+        assert_eq!(last.atom_id, "O");
+        assert_eq!(
+            structure.secondary[1].sheet_id,
+            structure.secondary[2].sheet_id
+        );
+    }
+
+    #[test]
+    fn keeps_secondary_structure_from_the_file() {
+        let helix = "HELIX    1   1 ALA A    3  ALA A    6  5\n";
+        let mut structure = load_fixture("dssp_records", helix);
+        assign_secondary_structure(&mut structure);
+        assert_eq!(ranges(&structure), ["Helix310 A3-A6"]);
+    }
+}
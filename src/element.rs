@@ -0,0 +1,60 @@
+use std::fmt::{self, Display, Formatter};
+
+#[rustfmt::skip]
+const SYMBOLS: [&str; 119] = [
+    "",
+    "H", "He", "Li", "Be", "B", "C", "N", "O", "F", "Ne", "Na", "Mg", "Al", "Si", "P", "S",
+    "Cl", "Ar", "K", "Ca", "Sc", "Ti", "V", "Cr", "Mn", "Fe", "Co", "Ni", "Cu", "Zn", "Ga",
+    "Ge", "As", "Se", "Br", "Kr", "Rb", "Sr", "Y", "Zr", "Nb", "Mo", "Tc", "Ru", "Rh", "Pd",
+    "Ag", "Cd", "In", "Sn", "Sb", "Te", "I", "Xe", "Cs", "Ba", "La", "Ce", "Pr", "Nd", "Pm",
+    "Sm", "Eu", "Gd", "Tb", "Dy", "Ho", "Er", "Tm", "Yb", "Lu", "Hf", "Ta", "W", "Re", "Os",
+    "Ir", "Pt", "Au", "Hg", "Tl", "Pb", "Bi", "Po", "At", "Rn", "Fr", "Ra", "Ac", "Th", "Pa",
+    "U", "Np", "Pu", "Am", "Cm", "Bk", "Cf", "Es", "Fm", "Md", "No", "Lr", "Rf", "Db", "Sg",
+    "Bh", "Hs", "Mt", "Ds", "Rg", "Cn", "Nh", "Fl", "Mc", "Lv", "Ts", "Og",
+];
+
+// An element, stored as its atomic number. Symbols that aren't in the periodic table,
+// like the "X" of an unknown atom or the "R#" of an SDF query atom, are element 0.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Element(u8);
+
+impl Element {
+    pub const UNKNOWN: Element = Element(0);
+    pub const H: Element = Element(1);
+    pub const C: Element = Element(6);
+
+    // Element symbols are upper case in PDB files ("CL"), but lower case
+    // in some others ("cl"), so the case is ignored
+    pub fn from_symbol(symbol: &str) -> Element {
+        let symbol = symbol.trim();
+        match SYMBOLS
+            .iter()
+            .skip(1)
+            .position(|s| s.eq_ignore_ascii_case(symbol))
+        {
+            Some(i) => Element(i as u8 + 1),
+            None => Element::UNKNOWN,
+        }
+    }
+
+    pub fn atomic_number(&self) -> usize {
+        self.0 as usize
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        SYMBOLS[self.atomic_number()]
+    }
+
+    pub fn is_known(&self) -> bool {
+        *self != Element::UNKNOWN
+    }
+}
+
+impl Display for Element {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.is_known() {
+            true => write!(f, "{}", self.symbol()),
+            false => write!(f, "?"),
+        }
+    }
+}
@@ -0,0 +1,310 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::ops::{Deref, Range};
+use std::str::FromStr;
+use std::sync::{LazyLock, Mutex};
+
+use crate::tessellate::Atom;
+
+// A chain id, component id or atom id. These are at most a few characters long, so they're
+// stored inline instead of each one being a separate allocation. Unused bytes are zero.
+// The rare ids that don't fit are kept in a table shared by all labels, and the label
+// stores their index in it after a 0xFF byte, which can't start a string.
+#[derive(Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Label([u8; 8]);
+
+const LONG_LABEL: u8 = 0xFF;
+
+// Ids that don't fit in a label, and the index of each one
+#[derive(Default)]
+struct LongLabels {
+    ids: Vec<&'static str>,
+    indexes: HashMap<&'static str, u32>,
+}
+
+static LONG_LABELS: LazyLock<Mutex<LongLabels>> = LazyLock::new(Default::default);
+
+impl Label {
+    pub fn as_str(&self) -> &str {
+        if self.0[0] == LONG_LABEL {
+            let index = u32::from_le_bytes([self.0[1], self.0[2], self.0[3], self.0[4]]);
+            return LONG_LABELS.lock().unwrap().ids[index as usize];
+        }
+        let len = self.0.iter().position(|b| *b == 0).unwrap_or(self.0.len());
+        std::str::from_utf8(&self.0[..len]).unwrap_or_default()
+    }
+
+    // The same long id always gets the same index, so labels can still be compared by
+    // their bytes. The ids are never freed, but there are only a few in a file.
+    fn long(s: &str) -> Label {
+        let mut long_labels = LONG_LABELS.lock().unwrap();
+        let index = match long_labels.indexes.get(s) {
+            Some(index) => *index,
+            None => {
+                let id: &'static str = Box::leak(s.into());
+                let index = long_labels.ids.len() as u32;
+                long_labels.ids.push(id);
+                long_labels.indexes.insert(id, index);
+                index
+            }
+        };
+
+        let mut bytes = [0u8; 8];
+        bytes[0] = LONG_LABEL;
+        bytes[1..5].copy_from_slice(&index.to_le_bytes());
+        Label(bytes)
+    }
+}
+
+impl FromStr for Label {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Label, String> {
+        let mut bytes = [0u8; 8];
+        if s.contains('\0') {
+            return Err(format!("Invalid identifier: {s}"));
+        }
+        if s.len() > bytes.len() {
+            return Ok(Label::long(s));
+        }
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+        Ok(Label(bytes))
+    }
+}
+
+// Ordered by the strings, since the bytes of a long id are an index
+impl Ord for Label {
+    fn cmp(&self, other: &Label) -> Ordering {
+        match self.0[0] == LONG_LABEL || other.0[0] == LONG_LABEL {
+            true => self.as_str().cmp(other.as_str()),
+            false => self.0.cmp(&other.0),
+        }
+    }
+}
+
+impl PartialOrd for Label {
+    fn partial_cmp(&self, other: &Label) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Deref for Label {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq<str> for Label {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Label {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl Display for Label {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Debug for Label {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+// Position of a residue in its chain. Residues that were inserted relative to a reference
+// numbering have an insertion code, like 52A and 52B between 52 and 53.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SeqId {
+    pub number: i32,
+    pub insertion: Option<char>,
+}
+
+impl FromStr for SeqId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SeqId, String> {
+        let s = s.trim();
+        let (number, insertion) = match s.chars().last() {
+            Some(c) if c.is_ascii_alphabetic() => (&s[..s.len() - 1], Some(c)),
+            _ => (s, None),
+        };
+        let number = number
+            .trim()
+            .parse::<i32>()
+            .map_err(|_| format!("Invalid sequence id: {s}"))?;
+        Ok(SeqId { number, insertion })
+    }
+}
+
+impl Display for SeqId {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.number)?;
+        if let Some(c) = self.insertion {
+            write!(f, "{c}")?;
+        }
+        Ok(())
+    }
+}
+
+// Models contain chains, which contain residues, which contain atoms. Each level is a
+// range into the next one down, so the atoms of every level are contiguous.
+pub struct Model {
+    pub chains: Range<usize>,
+    pub atoms: Range<usize>,
+}
+
+pub struct Chain<'a> {
+    pub id: &'a str,
+    pub model: usize,
+    pub residues: Range<usize>,
+    pub atoms: Range<usize>,
+}
+
+// A residue of a polymer, or a whole ligand or water molecule
+pub struct Residue<'a> {
+    pub name: &'a str, // component id, like "ALA" or "HOH"
+    pub seq_id: SeqId,
+    pub chain: usize,
+    pub atoms: Range<usize>,
+}
+
+// Model, chain id and sequence id of a residue
+type ResidueKey<'a> = (usize, &'a str, SeqId);
+
+pub struct Hierarchy<'a> {
+    pub atoms: &'a [Atom],
+    pub models: Vec<Model>,
+    pub chains: Vec<Chain<'a>>,
+    pub residues: Vec<Residue<'a>>,
+    // Residues that share a chain and sequence id, like alternate residues at the same
+    // position (microheterogeneity), are next to each other
+    lookup: HashMap<ResidueKey<'a>, Range<usize>>,
+}
+
+impl<'a> Hierarchy<'a> {
+    // Group atoms that are sorted by chain and residue. A new residue starts when the chain,
+    // sequence id or component changes. Residues without a sequence id (like the waters of
+    // a PDB file) are told apart by repeated atom ids instead. Alternate locations of an
+    // atom are part of the same residue.
+    pub fn new(atoms: &'a [Atom], models: impl IntoIterator<Item = Range<usize>>) -> Self {
+        let mut hierarchy = Hierarchy {
+            atoms,
+            models: Vec::new(),
+            chains: Vec::new(),
+            residues: Vec::new(),
+            lookup: HashMap::new(),
+        };
+
+        for (model, atom_range) in models.into_iter().enumerate() {
+            let first_chain = hierarchy.chains.len();
+            let mut atom_ids: HashSet<(Label, Option<char>)> = HashSet::new();
+
+            for i in atom_range.clone() {
+                let atom = &atoms[i];
+                let key = (atom.atom_id, atom.alt_id);
+
+                let new_chain = hierarchy.chains.len() == first_chain
+                    || hierarchy
+                        .chains
+                        .last()
+                        .is_some_and(|c| c.id != atom.chain_id.as_str());
+                let new_residue = new_chain
+                    || hierarchy.residues.last().is_some_and(|r| {
+                        r.seq_id != atom.sequence_id
+                            || r.name != atom.component_name.as_str()
+                            || (!atom.atom_id.is_empty() && atom_ids.contains(&key))
+                    });
+
+                if new_chain {
+                    hierarchy.chains.push(Chain {
+                        id: &atom.chain_id,
+                        model,
+                        residues: hierarchy.residues.len()..hierarchy.residues.len(),
+                        atoms: i..i,
+                    });
+                }
+                if new_residue {
+                    hierarchy.residues.push(Residue {
+                        name: &atom.component_name,
+                        seq_id: atom.sequence_id,
+                        chain: hierarchy.chains.len() - 1,
+                        atoms: i..i,
+                    });
+                    atom_ids.clear();
+                }
+                atom_ids.insert(key);
+
+                let num_residues = hierarchy.residues.len();
+                let chain = hierarchy.chains.last_mut().unwrap();
+                chain.residues.end = num_residues;
+                chain.atoms.end = i + 1;
+                hierarchy.residues.last_mut().unwrap().atoms.end = i + 1;
+            }
+
+            hierarchy.models.push(Model {
+                chains: first_chain..hierarchy.chains.len(),
+                atoms: atom_range,
+            });
+        }
+
+        for (index, residue) in hierarchy.residues.iter().enumerate() {
+            let chain = &hierarchy.chains[residue.chain];
+            let key = (chain.model, chain.id, residue.seq_id);
+            let range = hierarchy.lookup.entry(key).or_insert(index..index);
+            range.end = index + 1;
+        }
+
+        hierarchy
+    }
+
+    pub fn chains(&self, model: &Model) -> &[Chain<'a>] {
+        &self.chains[model.chains.clone()]
+    }
+
+    pub fn residues(&self, chain: &Chain) -> &[Residue<'a>] {
+        &self.residues[chain.residues.clone()]
+    }
+
+    // The residues at a position of a chain. There's more than one when the position
+    // was modeled as alternate residues.
+    pub fn find_residues(&self, model: usize, chain_id: &str, seq_id: SeqId) -> &[Residue<'a>] {
+        match self.lookup.get(&(model, chain_id, seq_id)) {
+            Some(range) => &self.residues[range.clone()],
+            None => &[],
+        }
+    }
+
+    // Every alternate location of an atom
+    pub fn find_atoms(
+        &self,
+        model: usize,
+        chain_id: &str,
+        seq_id: SeqId,
+        atom_id: Label,
+    ) -> impl Iterator<Item = usize> + '_ {
+        self.find_residues(model, chain_id, seq_id)
+            .iter()
+            .flat_map(|residue| residue.atoms.clone())
+            .filter(move |i| self.atoms[*i].atom_id == atom_id)
+    }
+
+    pub fn find_atom(
+        &self,
+        model: usize,
+        chain_id: &str,
+        seq_id: SeqId,
+        atom_id: Label,
+    ) -> Option<usize> {
+        self.find_atoms(model, chain_id, seq_id, atom_id).next()
+    }
+}
@@ -1,17 +1,189 @@
-use glam::{Mat4, Vec3, Vec4};
+use glam::{Mat3, Mat4, Vec3, Vec4};
 use indexmap::IndexMap;
 use itertools::Itertools;
-use memchr::memchr;
-use memchr::memmem::find_iter;
+use memchr::memmem;
+use memchr::{memchr, memchr_iter, memrchr};
 use memmap::{Mmap, MmapOptions};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
 use std::fs::File;
-use std::path::Path;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
-use crate::tessellate::{Atom, Bond, BondType, SecondaryStructure, SecondaryType, Structure};
+use crate::element::Element;
+use crate::hierarchy::{Hierarchy, Label, Residue, SeqId};
+use crate::tessellate::{
+    Assembly, Atom, Bond, BondType, Diagnostics, Frame, SecondaryStructure, SecondaryType,
+    Structure,
+};
 
 pub trait FileLoader: Send {
-    fn parse_file(&mut self, path: &Path) -> Result<Structure, String>;
+    // Returns every record in the file. Most formats only have one.
+    fn parse_file(&mut self, path: &Path) -> Result<Vec<Structure>, ParseError>;
+}
+
+// Why and where a file failed to parse. Lines and columns start at 1, and the table and
+// field are the mmCIF category and attribute (or a description of the field, for other formats).
+// Offsets into a file already have to fit in a u32, so lines and columns do too.
+#[derive(Debug, Default, Clone)]
+pub struct ParseError {
+    pub path: PathBuf,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    pub table: Option<String>,
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl ParseError {
+    pub fn new(message: impl Into<String>) -> ParseError {
+        ParseError {
+            message: message.into(),
+            ..Default::default()
+        }
+    }
+
+    fn in_file(self, path: &Path) -> ParseError {
+        ParseError {
+            path: path.to_path_buf(),
+            ..self
+        }
+    }
+
+    // Errors from a record or frame have lines relative to its start, so they're shifted
+    // by the line the record starts on. Errors that already have a line keep it.
+    fn at_line(self, line: usize) -> ParseError {
+        ParseError {
+            line: Some(self.line.map_or(line as u32, |l| l + line as u32 - 1)),
+            ..self
+        }
+    }
+
+    fn at_column(self, column: usize) -> ParseError {
+        ParseError {
+            column: self.column.or(Some(column as u32)),
+            ..self
+        }
+    }
+
+    fn in_field(self, table: Option<&str>, field: &str) -> ParseError {
+        ParseError {
+            table: table.map(String::from),
+            field: Some(field.to_string()),
+            ..self
+        }
+    }
+}
+
+impl From<String> for ParseError {
+    fn from(message: String) -> ParseError {
+        ParseError::new(message)
+    }
+}
+
+impl From<&str> for ParseError {
+    fn from(message: &str) -> ParseError {
+        ParseError::new(message)
+    }
+}
+
+impl From<ParseError> for String {
+    fn from(error: ParseError) -> String {
+        error.to_string()
+    }
+}
+
+// Formatted like compiler errors: `file.cif:12:40: Invalid number (_atom_site.Cartn_x)`
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if !self.path.as_os_str().is_empty() {
+            write!(f, "{}:", self.path.display())?;
+        }
+        if let Some(line) = self.line {
+            write!(f, "{line}:")?;
+            if let Some(column) = self.column {
+                write!(f, "{column}:")?;
+            }
+        }
+        if !self.path.as_os_str().is_empty() || self.line.is_some() {
+            write!(f, " ")?;
+        }
+        write!(f, "{}", self.message)?;
+        match (&self.table, &self.field) {
+            (Some(table), Some(field)) => write!(f, " (_{table}.{field})"),
+            (None, Some(field)) => write!(f, " ({field})"),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Whitespace separated fields of a line, with the column each one starts at
+fn split_fields(line: &str) -> Vec<(usize, &str)> {
+    let mut fields = Vec::new();
+    let mut start = None;
+    for (i, c) in line
+        .char_indices()
+        .chain(std::iter::once((line.len(), ' ')))
+    {
+        match (c.is_whitespace(), start) {
+            (true, Some(s)) => {
+                fields.push((s + 1, &line[s..i]));
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => {}
+        }
+    }
+    fields
+}
+
+fn parse<T: std::str::FromStr>(fields: &[(usize, &str)], index: usize) -> Result<T, ParseError> {
+    let (column, text) = fields
+        .get(index)
+        .ok_or_else(|| ParseError::new(format!("Expected at least {} fields", index + 1)))?;
+    text.parse::<T>()
+        .map_err(|_| ParseError::new(format!("Invalid value: {text}")).at_column(*column))
+}
+
+// Symbols that aren't in the periodic table, like the "X" of an unknown atom or the
+// "R#" of an SDF query atom, still load, but the atom has no element
+fn element(symbol: &str, diagnostics: &mut Diagnostics) -> Element {
+    let element = Element::from_symbol(symbol);
+    match symbol.trim() {
+        _ if element.is_known() => {}
+        "" => diagnostics.warn("Atoms without an element symbol"),
+        symbol => diagnostics.warn(format!("Unknown element symbol {symbol}")),
+    }
+    element
+}
+
+// Coordinates like "nan" and "inf" parse as numbers, but can't be drawn
+fn atom_position(x: f32, y: f32, z: f32) -> Result<Vec3, ParseError> {
+    let position = Vec3::new(x, y, z);
+    match position.is_finite() {
+        true => Ok(position),
+        false => Err(ParseError::new(format!("Invalid position: {x} {y} {z}"))),
+    }
+}
+
+// Whether the bonds of a macromolecular file leave out some of its connectivity, so the
+// rest has to come from distances. An atom without bonds is only expected when it's
+// the whole residue, like an ion or a water without hydrogens.
+fn missing_bonds(atoms: &[Atom], bonds: &[Bond]) -> bool {
+    let mut has_bonds = vec![false; atoms.len()];
+    for bond in bonds {
+        has_bonds[bond.src] = true;
+        has_bonds[bond.dst] = true;
+    }
+
+    let same_residue = |a: &(&Atom, bool), b: &(&Atom, bool)| {
+        (a.0.chain_id, a.0.sequence_id, a.0.component_name)
+            == (b.0.chain_id, b.0.sequence_id, b.0.component_name)
+    };
+    let atoms: Vec<(&Atom, bool)> = atoms.iter().zip(has_bonds).collect();
+    atoms
+        .chunk_by(same_residue)
+        .any(|residue| residue.len() > 1 && residue.iter().any(|(_, bonded)| !bonded))
 }
 
 fn split(lines: &str, sep: char, strip: bool) -> Vec<&str> {
@@ -21,381 +193,1846 @@ fn split(lines: &str, sep: char, strip: bool) -> Vec<&str> {
         .collect()
 }
 
-fn parse<T: std::str::FromStr>(v: &Vec<&str>, index: usize) -> Result<T, String> {
-    let element = v.get(index).ok_or(String::from("Missing value"))?;
-    element
-        .parse::<T>()
-        .map_err(|_| String::from("Invalid value"))
+pub struct SDFLoader {}
+
+// Types 5 to 8 are query bonds ("single or double", "any", ...) that only make sense
+// for substructure searches. Types 9 and 10 are only used in V3000 files.
+fn bond_type(order: usize) -> Result<BondType, String> {
+    match order {
+        1 => Ok(BondType::Single),
+        2 => Ok(BondType::Double),
+        3 => Ok(BondType::Triple),
+        4 => Ok(BondType::Aromatic),
+        9 => Ok(BondType::Dative),
+        10 => Ok(BondType::HBond),
+        m => Err(format!("Unreconized bond type: {m}")),
+    }
 }
 
-pub struct SDFLoader {}
+fn sdf_atom(element: Element, position: Vec3) -> Atom {
+    Atom {
+        is_ligand: true,
+        position,
+        element,
+        ..Default::default()
+    }
+}
 
-impl FileLoader for SDFLoader {
-    fn parse_file(&mut self, path: &Path) -> Result<Structure, String> {
-        let contents = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
-        let lines = split(&contents, '\n', false);
+// V2000 atom blocks encode the charge column as 1 = +3, 2 = +2, 3 = +1,
+// 4 = doublet radical, 5 = -1, 6 = -2, 7 = -3
+fn apply_charge_code(atom: &mut Atom, code: usize) {
+    match code {
+        1..=3 => atom.charge = 4 - code as i8,
+        4 => atom.radical = 2,
+        5..=7 => atom.charge = 4 - code as i8,
+        _ => {}
+    }
+}
 
-        let count_line = parse::<String>(&lines, 3)?;
-        let count_fields = split(&count_line, ' ', true);
-        let num_atoms = parse::<usize>(&count_fields, 0)?;
-        let num_bonds = parse::<usize>(&count_fields, 1)?;
+// The V2000 atom, bond and property lines have fixed columns. Numbers can fill their
+// columns, like the bond `106108  1` between atoms 106 and 108, so they can't be split
+// on whitespace.
+impl SDFLoader {
+    // `xxxxx.xxxxyyyyy.yyyyzzzzz.zzzz aaaddcccsss...`: the position, the element symbol,
+    // the mass difference and the charge code
+    fn parse_v2000_atom(line: &str, diagnostics: &mut Diagnostics) -> Result<Atom, ParseError> {
+        let mut atom = sdf_atom(
+            element(column(line, 32, 34), diagnostics),
+            atom_position(
+                parse_column::<f32>(line, 1, 10)?,
+                parse_column::<f32>(line, 11, 20)?,
+                parse_column::<f32>(line, 21, 30)?,
+            )?,
+        );
+        if !column(line, 37, 39).is_empty() {
+            apply_charge_code(&mut atom, parse_column::<usize>(line, 37, 39)?);
+        }
+        Ok(atom)
+    }
+
+    // `111222tttsss...`: the two atom numbers and the bond type
+    fn parse_v2000_bond(line: &str) -> Result<Bond, ParseError> {
+        let index = |start: usize| -> Result<usize, ParseError> {
+            let index = parse_column::<usize>(line, start, start + 2)?;
+            index
+                .checked_sub(1)
+                .ok_or_else(|| ParseError::new("Atom numbers start at 1").at_column(start))
+        };
+        Ok(Bond {
+            src: index(1)?,
+            dst: index(4)?,
+            bond_type: bond_type(parse_column::<usize>(line, 7, 9)?)
+                .map_err(|e| ParseError::new(e).at_column(7))?,
+        })
+    }
+
+    // `M  CHGnn8 aaa vvv aaa vvv ...`: the number of entries, then (atom, value) pairs
+    fn apply_v2000_property(atoms: &mut [Atom], line: &str) -> Result<(), ParseError> {
+        let count = parse_column::<usize>(line, 7, 9)?;
+        for i in 0..count {
+            let (atom_column, value_column) = (11 + i * 8, 15 + i * 8);
+            let index = parse_column::<usize>(line, atom_column, atom_column + 2)?;
+            let atom = index
+                .checked_sub(1)
+                .and_then(|i| atoms.get_mut(i))
+                .ok_or_else(|| {
+                    ParseError::new(format!("Property references missing atom {index}"))
+                        .at_column(atom_column)
+                })?;
+            let value_end = value_column + 2;
+            match column(line, 1, 6) {
+                "M  CHG" => atom.charge = parse_column(line, value_column, value_end)?,
+                "M  ISO" => atom.isotope = parse_column(line, value_column, value_end)?,
+                _ => atom.radical = parse_column(line, value_column, value_end)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_v2000_ctab(
+        lines: &[&str],
+        diagnostics: &mut Diagnostics,
+    ) -> Result<(Vec<Atom>, Vec<Bond>), ParseError> {
+        let line = |i: usize| -> Result<&str, ParseError> {
+            lines
+                .get(i)
+                .copied()
+                .ok_or_else(|| ParseError::new("Unexpected end of molecule").at_line(i + 1))
+        };
+
+        // `aaabbb...`: the number of atoms and bonds
+        let counts = line(3)?;
+        let num_atoms = parse_column::<usize>(counts, 1, 3).map_err(|e| e.at_line(4))?;
+        let num_bonds = parse_column::<usize>(counts, 4, 6).map_err(|e| e.at_line(4))?;
+        let block_end = num_atoms
+            .checked_add(num_bonds)
+            .and_then(|n| n.checked_add(4))
+            .filter(|end| *end <= lines.len())
+            .ok_or_else(|| {
+                let message = format!("Expected {num_atoms} atoms and {num_bonds} bonds");
+                ParseError::new(message).at_line(4)
+            })?;
 
         let mut atoms: Vec<Atom> = Vec::new();
         let mut bonds: Vec<Bond> = Vec::new();
 
-        for i in 0..num_atoms {
-            let line = parse::<String>(&lines, 4 + i)?;
-            let fields = split(&line, ' ', true);
-            atoms.push(Atom {
-                chain_id: String::new(),
-                sequence_id: String::new(),
-                component_name: String::new(),
-                atom_id: String::new(),
-                is_ligand: true,
-                position: Vec3::new(
-                    parse::<f32>(&fields, 0)?,
-                    parse::<f32>(&fields, 1)?,
-                    parse::<f32>(&fields, 2)?,
-                ),
-                element: parse::<String>(&fields, 3)?,
-            });
+        for i in 4..4 + num_atoms {
+            let atom =
+                Self::parse_v2000_atom(line(i)?, diagnostics).map_err(|e| e.at_line(i + 1))?;
+            atoms.push(atom);
+        }
+
+        for i in 4 + num_atoms..block_end {
+            let bond = Self::parse_v2000_bond(line(i)?).map_err(|e| e.at_line(i + 1))?;
+            for (atom, column) in [(bond.src, 1), (bond.dst, 4)] {
+                if atom >= num_atoms {
+                    let message = format!("Bond references missing atom {}", atom + 1);
+                    return Err(ParseError::new(message).at_line(i + 1).at_column(column));
+                }
+            }
+            bonds.push(bond);
+        }
+
+        // Property lines like `M  CHG  2   1   1   3  -1` list (atom, value) pairs.
+        // CHG and RAD lines supersede every charge and radical in the atom block.
+        let properties: Vec<(usize, &str)> = lines[block_end..]
+            .iter()
+            .enumerate()
+            .take_while(|(_, line)| !line.starts_with("M  END"))
+            .map(|(i, line)| (block_end + i, *line))
+            .filter(|(_, line)| matches!(column(line, 1, 6), "M  CHG" | "M  ISO" | "M  RAD"))
+            .collect();
+
+        let overrides = properties
+            .iter()
+            .any(|(_, line)| matches!(column(line, 1, 6), "M  CHG" | "M  RAD"));
+        if overrides {
+            for atom in atoms.iter_mut() {
+                atom.charge = 0;
+                atom.radical = 0;
+            }
+        }
+
+        for (i, line) in properties {
+            Self::apply_v2000_property(&mut atoms, line).map_err(|e| e.at_line(i + 1))?;
+        }
+
+        Ok((atoms, bonds))
+    }
+
+    // The V3000 connection table is made of `M  V30` lines grouped into blocks
+    // (`BEGIN ATOM` ... `END ATOM`). A trailing `-` continues a line onto the next one.
+    // Collections are added to the properties, named after the collection
+    fn parse_v3000_ctab(
+        lines: &[&str],
+        properties: &mut IndexMap<String, String>,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<(Vec<Atom>, Vec<Bond>), ParseError> {
+        // Each statement keeps the line it starts on, and whether it continues onto other lines
+        let mut statements: Vec<(usize, String, bool)> = Vec::new();
+        let mut continued = false;
+
+        for (i, line) in lines.iter().enumerate() {
+            let Some(content) = line.strip_prefix("M  V30 ") else {
+                continue;
+            };
+            let (content, continues) = match content.strip_suffix('-') {
+                Some(c) => (c, true),
+                None => (content, false),
+            };
+            match statements.last_mut() {
+                Some((_, last, multiline)) if continued => {
+                    last.push_str(content);
+                    *multiline = true;
+                }
+                // The prefix is kept so the columns of the fields match the file
+                _ => statements.push((i, format!("M  V30 {content}"), false)),
+            }
+            continued = continues;
+        }
+
+        let mut atoms: Vec<Atom> = Vec::new();
+        let mut bonds: Vec<Bond> = Vec::new();
+        let mut counts: Option<(usize, usize)> = None;
+        let mut block = String::new();
+
+        // Atom and bond indexes only have to be unique, not contiguous
+        let mut indexes: HashMap<usize, usize> = HashMap::new();
+        let mut bond_indexes: HashMap<usize, usize> = HashMap::new();
+
+        for (line, statement, multiline) in &statements {
+            let fields = split_fields(statement);
+            let fields = &fields[2..];
+            let mut parse_statement = || -> Result<(), ParseError> {
+                let atom_index = |indexes: &HashMap<usize, usize>, i: usize| {
+                    let index = parse::<usize>(fields, i)?;
+                    indexes.get(&index).copied().ok_or_else(|| {
+                        ParseError::new(format!("Bond references missing atom {index}"))
+                            .at_column(fields[i].0)
+                    })
+                };
+
+                match (fields.first().map(|f| f.1), block.as_str()) {
+                    (Some("BEGIN"), _) => block = fields.get(1).map_or("", |f| f.1).to_string(),
+                    (Some("END"), _) => block.clear(),
+                    (Some("COUNTS"), _) => {
+                        counts = Some((parse::<usize>(fields, 1)?, parse::<usize>(fields, 2)?))
+                    }
+
+                    (Some(_), "ATOM") => {
+                        indexes.insert(parse::<usize>(fields, 0)?, atoms.len());
+                        let mut atom = sdf_atom(
+                            element(&parse::<String>(fields, 1)?, diagnostics),
+                            atom_position(
+                                parse::<f32>(fields, 2)?,
+                                parse::<f32>(fields, 3)?,
+                                parse::<f32>(fields, 4)?,
+                            )?,
+                        );
+
+                        // Optional properties follow the atom-atom mapping as KEY=VALUE pairs
+                        for (column, property) in fields.iter().skip(6) {
+                            let invalid = || {
+                                ParseError::new(format!("Invalid atom property: {property}"))
+                                    .at_column(*column)
+                            };
+                            match property.split_once('=') {
+                                Some(("CHG", v)) => {
+                                    atom.charge = v.parse().map_err(|_| invalid())?
+                                }
+                                Some(("MASS", v)) => {
+                                    atom.isotope = v.parse().map_err(|_| invalid())?
+                                }
+                                Some(("RAD", v)) => {
+                                    atom.radical = v.parse().map_err(|_| invalid())?
+                                }
+                                _ => {}
+                            }
+                        }
+                        atoms.push(atom);
+                    }
+
+                    (Some(_), "BOND") => {
+                        bond_indexes.insert(parse::<usize>(fields, 0)?, bonds.len());
+                        bonds.push(Bond {
+                            bond_type: bond_type(parse::<usize>(fields, 1)?)
+                                .map_err(|e| ParseError::new(e).at_column(fields[1].0))?,
+                            src: atom_index(&indexes, 2)?,
+                            dst: atom_index(&indexes, 3)?,
+                        })
+                    }
+
+                    // A named set of atoms and bonds, like `MDLV30/STEABS ATOMS=(2 3 5)` for
+                    // a stereo group or `MDLV30/HILITE` for highlighted atoms. The members
+                    // are numbered by their position in the file.
+                    (Some(name), "COLLECTION") => {
+                        let mut members: Vec<String> = Vec::new();
+                        let mut k = 1;
+                        while k < fields.len() {
+                            let (column, field) = fields[k];
+                            let invalid = || {
+                                ParseError::new(format!("Invalid collection members: {field}"))
+                                    .at_column(column)
+                            };
+                            let (kind, count) = field.split_once("=(").ok_or_else(invalid)?;
+                            // An empty list is written as `ATOMS=(0)`
+                            let ids = match count {
+                                "0)" => &fields[..0],
+                                count => {
+                                    let count = count.parse::<usize>().map_err(|_| invalid())?;
+                                    let ids = fields.get(k + 1..k + 1 + count);
+                                    ids.filter(|ids| !ids.is_empty()).ok_or_else(invalid)?
+                                }
+                            };
+
+                            k += 1 + ids.len();
+
+                            // Highlighted S-groups and 3D features aren't loaded
+                            let (map, member) = match kind {
+                                "ATOMS" => (&indexes, "atom"),
+                                "BONDS" => (&bond_indexes, "bond"),
+                                _ => continue,
+                            };
+                            let mut positions: Vec<String> = Vec::new();
+                            for (i, (column, id)) in ids.iter().enumerate() {
+                                let id = match i + 1 == ids.len() {
+                                    true => id.strip_suffix(')').ok_or_else(invalid)?,
+                                    false => id,
+                                };
+                                let position = id.parse::<usize>().ok().and_then(|id| map.get(&id));
+                                let position = position.ok_or_else(|| {
+                                    let message =
+                                        format!("Collection references missing {member} {id}");
+                                    ParseError::new(message).at_column(*column)
+                                })?;
+                                positions.push((position + 1).to_string());
+                            }
+                            if !positions.is_empty() {
+                                members.push(format!(
+                                    "{} {}",
+                                    kind.to_lowercase(),
+                                    positions.join(" ")
+                                ));
+                            }
+                        }
+
+                        if members.is_empty() {
+                            return Ok(());
+                        }
+                        let value = members.join("; ");
+                        properties
+                            .entry(name.to_string())
+                            .and_modify(|v| *v = format!("{v}\n{value}"))
+                            .or_insert(value);
+                    }
+
+                    // The other blocks (S-groups, 3D features, ...) have no equivalent in the
+                    // V2000 connection table, so they're skipped
+                    _ => {}
+                }
+                Ok(())
+            };
+
+            // Columns past the first line of a statement can't be traced back to the file
+            parse_statement().map_err(|e| ParseError {
+                column: e.column.filter(|_| !multiline),
+                ..e.at_line(line + 1)
+            })?;
         }
 
-        for i in 0..num_bonds {
-            let line = parse::<String>(&lines, 4 + num_atoms + i)?;
-            let fields = split(&line, ' ', true);
-            bonds.push(Bond {
-                src: parse::<usize>(&fields, 0)? - 1,
-                dst: parse::<usize>(&fields, 1)? - 1,
-                bond_type: match parse::<usize>(&fields, 2)? {
-                    1 => BondType::Single,
-                    2 => BondType::Double,
-                    3 => BondType::Triple,
-                    m => return Err(format!("Unreconized bond type: {m}")),
-                },
+        match counts {
+            Some((num_atoms, num_bonds))
+                if num_atoms == atoms.len() && num_bonds == bonds.len() =>
+            {
+                Ok((atoms, bonds))
+            }
+            Some((num_atoms, num_bonds)) => Err(ParseError::new(format!(
+                "Expected {num_atoms} atoms and {num_bonds} bonds, found {} and {}",
+                atoms.len(),
+                bonds.len()
+            ))),
+            None => Err(ParseError::new("Missing COUNTS line")),
+        }
+    }
+
+    // Parse a single molfile and the data items that follow it
+    fn parse_record(lines: &[&str]) -> Result<Structure, ParseError> {
+        let count_line = lines
+            .get(3)
+            .ok_or_else(|| ParseError::new("Missing counts line").at_line(lines.len()))?;
+        let mut diagnostics = Diagnostics::default();
+        let mut properties: IndexMap<String, String> = IndexMap::new();
+        let (atoms, bonds) = if count_line.contains("V3000") {
+            Self::parse_v3000_ctab(lines, &mut properties, &mut diagnostics)?
+        } else {
+            Self::parse_v2000_ctab(lines, &mut diagnostics)?
+        };
+
+        // Data items come after the molfile. Each one is a header line like
+        // `> <NAME>` or `>  25  <NAME> (MD-08974)`, then value lines up until a blank line.
+        // The name is optional, so items like `>  DT13` are keyed by their field number
+        // or registry number instead.
+        let data_start = lines
+            .iter()
+            .position(|line| line.starts_with("M  END"))
+            .map_or(lines.len(), |i| i + 1);
+        let mut i = data_start;
+
+        while i < lines.len() {
+            let header = lines[i];
+            i += 1;
+            if !header.starts_with('>') {
+                continue;
+            }
+
+            let name = header.find('<').and_then(|start| {
+                let end = header[start..].find('>')?;
+                Some(&header[start + 1..start + end])
+            });
+            let mut fields = header[1..].split_whitespace();
+            let number = fields.clone().find(|f| {
+                f.strip_prefix("DT")
+                    .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
             });
+            let registry = fields.find_map(|f| f.strip_prefix('(')?.strip_suffix(')'));
+
+            let mut values: Vec<&str> = Vec::new();
+            while i < lines.len() && !lines[i].trim().is_empty() {
+                values.push(lines[i]);
+                i += 1;
+            }
+            match name.or(number).or(registry) {
+                Some(name) => {
+                    properties.insert(name.to_string(), values.join("\n"));
+                }
+                None => {
+                    diagnostics.warn("Data items without a name, field number or registry number")
+                }
+            }
         }
 
         Ok(Structure {
+            name: lines
+                .first()
+                .map_or(String::new(), |s| s.trim().to_string()),
             atoms,
             bonds,
+            properties,
+            diagnostics,
             ..Default::default()
         })
     }
 }
 
-#[derive(Debug, Clone)]
-pub enum Token {
+impl FileLoader for SDFLoader {
+    fn parse_file(&mut self, path: &Path) -> Result<Vec<Structure>, ParseError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| ParseError::new(err.to_string()).in_file(path))?;
+        let lines: Vec<&str> = contents.lines().collect();
+
+        // Records are separated by `$$$$`. A plain molfile has no separator at all.
+        let mut structures: Vec<Structure> = Vec::new();
+        let mut start = 0;
+        for record in lines.split(|line| line.trim_end() == "$$$$") {
+            let record_start = start;
+            start += record.len() + 1;
+            if record.iter().all(|line| line.trim().is_empty()) {
+                continue;
+            }
+            let structure = Self::parse_record(record).map_err(|e| ParseError {
+                message: format!("Molecule {}: {}", structures.len() + 1, e.message),
+                ..e.at_line(record_start + 1).in_file(path)
+            })?;
+            structures.push(structure);
+        }
+
+        if structures.is_empty() {
+            return Err(ParseError::new("File contains no molecules").in_file(path));
+        }
+        Ok(structures)
+    }
+}
+
+// Extract a fixed width field from a PDB record. Columns are 1-indexed and inclusive,
+// matching the format specification. Fields past the end of a short line are empty.
+fn column(line: &str, start: usize, end: usize) -> &str {
+    line.get(start - 1..end.min(line.len()))
+        .unwrap_or("")
+        .trim()
+}
+
+fn parse_column<T: std::str::FromStr>(
+    line: &str,
+    start: usize,
+    end: usize,
+) -> Result<T, ParseError> {
+    let text = column(line, start, end);
+    text.parse::<T>().map_err(|_| {
+        ParseError::new(format!("Invalid value in columns {start}-{end}: {text}")).at_column(start)
+    })
+}
+
+// Chain id and sequence id of a residue, where the chain id is in the given column and the
+// sequence id is in the four columns starting at `seq`, followed by the insertion code
+fn residue_id(line: &str, chain: usize, seq: usize) -> Result<(Label, SeqId), ParseError> {
+    Ok((
+        column(line, chain, chain)
+            .parse::<Label>()
+            .map_err(|e| ParseError::new(e).at_column(chain))?,
+        SeqId {
+            number: parse_column::<i32>(line, seq, seq + 3)?,
+            insertion: column(line, seq + 4, seq + 4).chars().next(),
+        },
+    ))
+}
+
+// PDB helix classes. 1 is a right-handed alpha helix, and the rare ones in between
+// are gamma, omega and left-handed helices.
+fn helix_type(class: Option<u8>) -> SecondaryType {
+    match class {
+        None | Some(1) => SecondaryType::AlphaHelix,
+        Some(3) => SecondaryType::PiHelix,
+        Some(5) => SecondaryType::Helix310,
+        Some(10) => SecondaryType::PolyprolineHelix,
+        Some(_) => SecondaryType::OtherHelix,
+    }
+}
+
+// Secondary structure of a `struct_conf` row, from a type like HELX_RH_3T_P (a
+// right-handed 3-10 helix in a protein). The generic helix types leave it to the class.
+fn conf_type(conf_type_id: &str, helix_class: Option<u8>) -> Option<SecondaryType> {
+    Some(match conf_type_id.to_ascii_uppercase().as_str() {
+        "HELX_P" | "HELX_OT_P" | "HELX_RH_P" | "HELX_RH_OT_P" => helix_type(helix_class),
+        "HELX_RH_AL_P" => SecondaryType::AlphaHelix,
+        "HELX_RH_3T_P" => SecondaryType::Helix310,
+        "HELX_RH_PI_P" => SecondaryType::PiHelix,
+        "HELX_RH_PP_P" | "HELX_LH_PP_P" => SecondaryType::PolyprolineHelix,
+        "STRN" => SecondaryType::BetaSheet,
+        "BEND" => SecondaryType::Bend,
+        id if id.starts_with("HELX_") => SecondaryType::OtherHelix,
+        id if id.starts_with("TURN_") => SecondaryType::Turn,
+        _ => return None,
+    })
+}
+
+// Atoms of a secondary structure range, from the first atom of its first residue to the
+// last atom of its last residue. Both residues have to be in the same chain, in order.
+fn secondary_atoms(
+    (start_chain, start): (&str, Range<usize>),
+    (end_chain, end): (&str, Range<usize>),
+) -> Result<(usize, usize), String> {
+    if start_chain != end_chain {
+        return Err(format!(
+            "Range starts in chain {start_chain} and ends in chain {end_chain}"
+        ));
+    }
+    if end.end <= start.start {
+        return Err(String::from("Range ends before it starts"));
+    }
+    Ok((start.start, end.end - 1))
+}
+
+// First and last residue of a HELIX or SHEET record, and the line it's on for diagnostics
+type ResidueRange = ((Label, SeqId), (Label, SeqId), SecondaryStructure, usize);
+
+pub struct PDBLoader {}
+
+impl PDBLoader {
+    fn parse_atom(line: &str, diagnostics: &mut Diagnostics) -> Result<Atom, ParseError> {
+        // Older files leave the element columns blank. The atom name then encodes
+        // the element right justified in columns 13-14 (" CA " is carbon, "CA  " is calcium)
+        let mut symbol = column(line, 77, 78);
+        if symbol.is_empty() {
+            let name = line.get(12..14).unwrap_or("");
+            symbol = match name.chars().next() {
+                Some(c) if c == ' ' || c.is_ascii_digit() => &name[1..],
+                _ => name,
+            };
+        }
+
+        // The charge is written as "2+" or "1-"
+        let charge = column(line, 79, 80);
+        let charge = match (charge.get(..1), charge.get(1..)) {
+            (Some(n), Some("+")) => n.parse::<i8>().unwrap_or(0),
+            (Some(n), Some("-")) => -n.parse::<i8>().unwrap_or(0),
+            _ => 0,
+        };
+
+        let (chain_id, sequence_id) = residue_id(line, 22, 23)?;
+        Ok(Atom {
+            chain_id,
+            sequence_id,
+            component_name: column(line, 18, 20)
+                .parse()
+                .map_err(|e: String| ParseError::new(e).at_column(18))?,
+            atom_id: column(line, 13, 16)
+                .parse()
+                .map_err(|e: String| ParseError::new(e).at_column(13))?,
+            element: element(symbol, diagnostics),
+            is_ligand: line.starts_with("HETATM"),
+            position: atom_position(
+                parse_column::<f32>(line, 31, 38)?,
+                parse_column::<f32>(line, 39, 46)?,
+                parse_column::<f32>(line, 47, 54)?,
+            )?,
+            charge,
+            alt_id: column(line, 17, 17).chars().next(),
+            occupancy: parse_column::<f32>(line, 55, 60).ok(),
+            b_factor: parse_column::<f32>(line, 61, 66).ok(),
+            ..Default::default()
+        })
+    }
+
+    // Parse a `REMARK 350 BIOMTn` row into the nth row of a transformation matrix
+    fn parse_biomt_row(line: &str) -> Result<(usize, [f32; 4]), ParseError> {
+        let fields = split_fields(line);
+        let fields = fields.get(2..).unwrap_or_default(); // skip `REMARK 350`
+        let row = fields
+            .first()
+            .and_then(|(_, s)| s.strip_prefix("BIOMT"))
+            .and_then(|s| s.parse::<usize>().ok())
+            .filter(|row| (1..=3).contains(row))
+            .ok_or_else(|| ParseError::new(format!("Invalid BIOMT record: {line}")))?;
+        Ok((
+            row,
+            [
+                parse::<f32>(fields, 2)?,
+                parse::<f32>(fields, 3)?,
+                parse::<f32>(fields, 4)?,
+                parse::<f32>(fields, 5)?,
+            ],
+        ))
+    }
+}
+
+impl FileLoader for PDBLoader {
+    fn parse_file(&mut self, path: &Path) -> Result<Vec<Structure>, ParseError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| ParseError::new(err.to_string()).in_file(path))?;
+
+        let mut atoms: Vec<Atom> = Vec::new();
+        let mut bonds: Vec<Bond> = Vec::new();
+        let mut secondary: Vec<SecondaryStructure> = Vec::new();
+        let mut assemblies: Vec<Assembly> = Vec::new();
+
+        // Serial numbers aren't guaranteed to be contiguous, so map them to atom indexes
+        let mut serials: HashMap<usize, usize> = HashMap::new();
+        // HELIX and SHEET records come before the atoms they reference
+        let mut ranges: Vec<ResidueRange> = Vec::new();
+        let mut bonded: HashSet<(usize, usize)> = HashSet::new();
+        let mut diagnostics = Diagnostics::default();
+
+        // Chains that the following BIOMT rows apply to
+        let mut assembly_chains: Vec<String> = Vec::new();
+        let mut rows = [[0.0f32; 4]; 3];
+        let mut prev_was_biomt = false;
+        // Only the first model is loaded, but the CONECT records come after the last one
+        let mut past_first_model = false;
+
+        for (n, line) in contents.lines().enumerate() {
+            let at_line = |e: ParseError| e.at_line(n + 1).in_file(path);
+            let record = column(line, 1, 6);
+            match record {
+                "ATOM" | "HETATM" if !past_first_model => {
+                    let atom = Self::parse_atom(line, &mut diagnostics).map_err(at_line)?;
+                    if let Ok(serial) = parse_column::<usize>(line, 7, 11) {
+                        serials.insert(serial, atoms.len());
+                    }
+                    atoms.push(atom);
+                }
+
+                "CONECT" => {
+                    let src = parse_column::<usize>(line, 7, 11).map_err(at_line)?;
+                    for (start, end) in [(12, 16), (17, 21), (22, 26), (27, 31)] {
+                        let Ok(dst) = parse_column::<usize>(line, start, end) else {
+                            continue;
+                        };
+
+                        // Atoms outside of the first model aren't loaded
+                        let (Some(&a), Some(&b)) = (serials.get(&src), serials.get(&dst)) else {
+                            continue;
+                        };
+
+                        // Each bond is listed under both of its atoms
+                        if bonded.insert((a.min(b), a.max(b))) {
+                            bonds.push(Bond {
+                                src: a,
+                                dst: b,
+                                bond_type: BondType::Single,
+                            });
+                        }
+                    }
+                }
+
+                "HELIX" => ranges.push((
+                    residue_id(line, 20, 22).map_err(at_line)?,
+                    residue_id(line, 32, 34).map_err(at_line)?,
+                    SecondaryStructure {
+                        struct_type: helix_type(parse_column::<u8>(line, 39, 40).ok()),
+                        ..Default::default()
+                    },
+                    n + 1,
+                )),
+
+                "SHEET" => ranges.push((
+                    residue_id(line, 22, 23).map_err(at_line)?,
+                    residue_id(line, 33, 34).map_err(at_line)?,
+                    SecondaryStructure {
+                        struct_type: SecondaryType::BetaSheet,
+                        sheet_id: Some(column(line, 12, 14).to_string()),
+                        ..Default::default()
+                    },
+                    n + 1,
+                )),
+
+                "REMARK" if column(line, 8, 10) == "350" => {
+                    let text = line.get(10..).unwrap_or("").trim();
+
+                    if let Some(id) = text.strip_prefix("BIOMOLECULE:") {
+                        assemblies.push(Assembly {
+                            id: id.trim().to_string(),
+                            ..Default::default()
+                        });
+                        assembly_chains.clear();
+                        prev_was_biomt = false;
+                        continue;
+                    }
+                    let Some(assembly) = assemblies.last_mut() else {
+                        continue;
+                    };
+
+                    // Use the same vocabulary as mmCIF's pdbx_struct_assembly
+                    let author_unit = text.strip_prefix("AUTHOR DETERMINED BIOLOGICAL UNIT:");
+                    let software_unit =
+                        text.strip_prefix("SOFTWARE DETERMINED QUATERNARY STRUCTURE:");
+                    if let Some(unit) = author_unit {
+                        assembly.details = String::from("author_defined_assembly");
+                        assembly.oligomeric = unit.trim().to_lowercase();
+                        continue;
+                    }
+                    if let Some(unit) = software_unit {
+                        if assembly.details.is_empty() {
+                            assembly.details = String::from("software_defined_assembly");
+                            assembly.oligomeric = unit.trim().to_lowercase();
+                        } else {
+                            assembly.details = String::from("author_and_software_defined_assembly");
+                        }
+                        continue;
+                    }
+
+                    let chain_list = text
+                        .strip_prefix("APPLY THE FOLLOWING TO CHAINS:")
+                        .or(text.strip_prefix("AND CHAINS:"));
+                    if let Some(list) = chain_list {
+                        // A new list of chains after some BIOMT rows starts a new group
+                        if prev_was_biomt {
+                            assembly_chains.clear();
+                        }
+                        assembly_chains.extend(
+                            split(list, ',', true)
+                                .iter()
+                                .map(|s| s.trim().to_string())
+                                .filter(|s| !s.is_empty()),
+                        );
+                        prev_was_biomt = false;
+                    } else if text.starts_with("BIOMT") {
+                        let (row, values) = Self::parse_biomt_row(line).map_err(at_line)?;
+                        rows[row - 1] = values;
+                        prev_was_biomt = true;
+
+                        if row == 3 {
+                            let transform = Mat4::from_cols(
+                                Vec4::new(rows[0][0], rows[1][0], rows[2][0], 0.0),
+                                Vec4::new(rows[0][1], rows[1][1], rows[2][1], 0.0),
+                                Vec4::new(rows[0][2], rows[1][2], rows[2][2], 0.0),
+                                Vec4::new(rows[0][3], rows[1][3], rows[2][3], 1.0),
+                            );
+                            for chain in &assembly_chains {
+                                assembly.chain_copies.push((chain.clone(), transform));
+                            }
+                        }
+                    }
+                }
+
+                "ENDMDL" => past_first_model = true,
+
+                _ => {}
+            }
+        }
+
+        // Residue ranges that point to residues without coordinates are skipped
+        let hierarchy = Hierarchy::new(&atoms, std::iter::once(0..atoms.len()));
+        let residue = |chain_id: &str, seq_id: SeqId| {
+            let residues = hierarchy.find_residues(0, chain_id, seq_id);
+            residues.first().map(|r| r.atoms.clone())
+        };
+        for ((start_chain, start_seq), (end_chain, end_seq), range, n) in ranges {
+            let record = match range.struct_type.is_helix() {
+                true => "HELIX",
+                false => "SHEET",
+            };
+            let start = residue(&start_chain, start_seq);
+            let end = residue(&end_chain, end_seq);
+            let (Some(start), Some(end)) = (start, end) else {
+                let error = ParseError::new(format!("{record} refers to a missing residue"));
+                diagnostics.warn(error.at_line(n).to_string());
+                continue;
+            };
+            match secondary_atoms((&start_chain, start), (&end_chain, end)) {
+                Ok((start, end)) => secondary.push(SecondaryStructure {
+                    start,
+                    end,
+                    ..range
+                }),
+                Err(message) => {
+                    let error = ParseError::new(format!("{record}: {message}"));
+                    diagnostics.warn(error.at_line(n).to_string());
+                }
+            }
+        }
+
+        Ok(vec![Structure {
+            // CONECT records are usually only written for ligands, if at all
+            infer_bonds: missing_bonds(&atoms, &bonds),
+            atoms,
+            bonds,
+            secondary,
+            assemblies,
+            diagnostics,
+            ..Default::default()
+        }])
+    }
+}
+
+// Split an extended XYZ comment line into key=value pairs. Values can be quoted,
+// and keys without a value are boolean flags.
+fn parse_key_values(line: &str) -> Vec<(String, String)> {
+    let mut pairs: Vec<(String, String)> = Vec::new();
+    let mut rest = line.trim();
+
+    while !rest.is_empty() {
+        let key_end = rest
+            .find(|c: char| c == '=' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let key = rest[..key_end].to_string();
+        rest = rest[key_end..].trim_start();
+
+        let Some(value) = rest.strip_prefix('=') else {
+            pairs.push((key, String::from("T")));
+            continue;
+        };
+
+        let value = value.trim_start();
+        let (value, remaining) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                (&quoted[..end], quoted.get(end + 1..).unwrap_or(""))
+            }
+            None => {
+                let end = value.find(char::is_whitespace).unwrap_or(value.len());
+                (&value[..end], &value[end..])
+            }
+        };
+        pairs.push((key, value.to_string()));
+        rest = remaining.trim_start();
+    }
+
+    pairs
+}
+
+pub struct XYZLoader {}
+
+impl XYZLoader {
+    // Parse one frame: the atom count, a comment line, then one line per atom.
+    // Returns the frame and the number of lines it took up.
+    fn parse_frame(
+        lines: &[&str],
+        atoms: &mut Vec<Atom>,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<(Frame, usize), ParseError> {
+        let num_atoms = lines[0]
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| ParseError::new(format!("Invalid atom count: {}", lines[0])).at_line(1))?;
+        let comment = lines.get(1).copied().unwrap_or("");
+
+        let mut frame = Frame {
+            atoms: atoms.len()..atoms.len(),
+            ..Default::default()
+        };
+
+        // Plain XYZ files have free form comments, while extended XYZ files
+        // have key=value pairs that describe the cell and the atom columns
+        let mut properties = String::from("species:S:1:pos:R:3");
+        if comment.contains('=') {
+            for (key, value) in parse_key_values(comment) {
+                match key.as_str() {
+                    "Lattice" => {
+                        let v = split(&value, ' ', true)
+                            .iter()
+                            .map(|s| s.parse::<f32>())
+                            .collect::<Result<Vec<f32>, _>>()
+                            .ok()
+                            .filter(|v| v.len() == 9)
+                            .ok_or_else(|| {
+                                ParseError::new(format!("Invalid lattice: {value}"))
+                                    .at_line(2)
+                                    .in_field(None, "Lattice")
+                            })?;
+                        frame.cell = Some(Mat3::from_cols(
+                            Vec3::new(v[0], v[1], v[2]),
+                            Vec3::new(v[3], v[4], v[5]),
+                            Vec3::new(v[6], v[7], v[8]),
+                        ));
+                    }
+                    "Properties" => properties = value,
+                    _ => {
+                        frame.properties.insert(key, value);
+                    }
+                }
+            }
+        } else if !comment.trim().is_empty() {
+            frame
+                .properties
+                .insert(String::from("Comment"), comment.trim().to_string());
+        }
+
+        // Properties are name:type:count triples, each of which takes up `count` columns
+        let mut species = None;
+        let mut position = None;
+        let mut column = 0;
+        let invalid_properties = || {
+            ParseError::new(format!("Invalid properties: {properties}"))
+                .at_line(2)
+                .in_field(None, "Properties")
+        };
+        for triple in split(&properties, ':', false).chunks(3) {
+            let count = triple
+                .get(2)
+                .and_then(|count| count.parse::<usize>().ok())
+                .ok_or_else(invalid_properties)?;
+            match triple[0] {
+                "species" => species = Some(column),
+                "pos" => position = Some(column),
+                _ => {}
+            }
+            column += count;
+        }
+        let (species, position) = species.zip(position).ok_or_else(|| {
+            ParseError::new(format!(
+                "Properties is missing species or pos: {properties}"
+            ))
+            .at_line(2)
+            .in_field(None, "Properties")
+        })?;
+
+        for i in 0..num_atoms {
+            let line = lines.get(2 + i).ok_or_else(|| {
+                ParseError::new(format!("Expected {num_atoms} atoms, found {i}")).at_line(3 + i)
+            })?;
+            let fields = split_fields(line);
+            let mut atom = || -> Result<Atom, ParseError> {
+                Ok(Atom {
+                    element: element(&parse::<String>(&fields, species)?, diagnostics),
+                    position: atom_position(
+                        parse::<f32>(&fields, position)?,
+                        parse::<f32>(&fields, position + 1)?,
+                        parse::<f32>(&fields, position + 2)?,
+                    )?,
+                    is_ligand: true,
+                    ..Default::default()
+                })
+            };
+            atoms.push(atom().map_err(|e| e.at_line(3 + i))?);
+        }
+        frame.atoms.end = atoms.len();
+
+        Ok((frame, 2 + num_atoms))
+    }
+}
+
+impl FileLoader for XYZLoader {
+    fn parse_file(&mut self, path: &Path) -> Result<Vec<Structure>, ParseError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| ParseError::new(err.to_string()).in_file(path))?;
+        let lines: Vec<&str> = contents.lines().collect();
+
+        // Consecutive frames make up a trajectory
+        let mut atoms: Vec<Atom> = Vec::new();
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut diagnostics = Diagnostics::default();
+        let mut i = 0;
+
+        while i < lines.len() {
+            if lines[i].trim().is_empty() {
+                i += 1;
+                continue;
+            }
+            let (frame, num_lines) = Self::parse_frame(&lines[i..], &mut atoms, &mut diagnostics)
+                .map_err(|e| ParseError {
+                message: format!("Frame {}: {}", frames.len() + 1, e.message),
+                ..e.at_line(i + 1).in_file(path)
+            })?;
+            frames.push(frame);
+            i += num_lines;
+        }
+
+        if frames.is_empty() {
+            return Err(ParseError::new("File contains no atoms").in_file(path));
+        }
+
+        Ok(vec![Structure {
+            atoms,
+            frames,
+            diagnostics,
+            infer_bonds: true,
+            ..Default::default()
+        }])
+    }
+}
+
+// Where a value is in its data block. Values are only decoded when they're read,
+// so the tokens of a large table don't each need an allocation. The null values
+// `?` (unknown) and `.` (inapplicable) have no text, so they point past the end of
+// any block that can be parsed.
+#[derive(Debug, Clone, Copy)]
+struct Span {
+    start: u32,
+    len: u32,
+}
+
+impl Span {
+    const UNKNOWN: Span = Span {
+        start: u32::MAX,
+        len: 0,
+    };
+    const INAPPLICABLE: Span = Span {
+        start: u32::MAX - 1,
+        len: 0,
+    };
+
+    fn is_null(&self) -> bool {
+        self.start >= Span::INAPPLICABLE.start
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Token<'a> {
     TableStart,
-    Label((String, String)), // block name, attribute name
-    Value(String),
+    Label(&'a str), // data name without the leading underscore
+    Value(Span),
+    SaveStart, // save_name
+    SaveEnd,   // save_
     Eof,
 }
 
-impl Token {
-    fn new(data: &str) -> Token {
-        if data == "loop_" {
-            return Token::TableStart;
+// Whether the bytes start with a reserved word like `data_`, which are case insensitive
+fn starts_with_keyword(bytes: &[u8], keyword: &[u8]) -> bool {
+    bytes.len() >= keyword.len() && bytes[..keyword.len()].eq_ignore_ascii_case(keyword)
+}
+
+// Split a data name into its category and attribute, like `atom_site.Cartn_x`. Names
+// from DDL1 dictionaries (`cell_length_a`) have no period, so they have no category.
+fn split_label(name: &str) -> (&str, &str) {
+    name.split_once('.').unwrap_or(("", name))
+}
+
+// Splits a data block into tokens, following the CIF 1.1 grammar. Offsets are relative
+// to the start of the block.
+struct Lexer<'a> {
+    file: &'a [u8],
+    bytes: &'a [u8], // the data block
+    offset: usize,   // where the data block starts in the file
+    i: usize,
+    start: usize, // where the current token starts
+}
+
+impl<'a> Lexer<'a> {
+    // An error at an offset in the data block
+    fn error_at(&self, start: usize, message: impl Into<String>) -> ParseError {
+        let (line, column) = location(self.file, self.offset + start);
+        ParseError::new(message).at_line(line).at_column(column)
+    }
+
+    // An error at the current token
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        self.error_at(self.start, message)
+    }
+
+    fn span(&self, start: usize, end: usize) -> Span {
+        Span {
+            start: start as u32,
+            len: (end - start) as u32,
         }
+    }
+
+    fn next_token(&mut self) -> Result<Token<'a>, ParseError> {
+        let bytes = self.bytes;
+
+        while self.i < bytes.len() {
+            let start = self.i;
+            self.start = start;
+            let line_start = start == 0 || bytes[start - 1] == b'\n';
+
+            // Comments run to the end of the line. A # inside of a value is part of the value.
+            if bytes[start] == b'#' {
+                self.i += memchr(b'\n', &bytes[start..]).unwrap_or(bytes.len() - start);
+                continue;
+            }
 
-        if let Some(rest) = data.strip_prefix('_') {
-            let dot = rest.find('.').unwrap();
-            return Token::Label((rest[..dot].to_string(), rest[dot + 1..].to_string()));
+            if bytes[start].is_ascii_whitespace() {
+                self.i += 1;
+                continue;
+            }
+
+            // A text field starts with a ; at the start of a line and ends at the next
+            // line that starts with a ;. Neither the ; nor the line break before it are
+            // part of the value.
+            if bytes[start] == b';' && line_start {
+                let end = memmem::find(&bytes[start..], b"\n;")
+                    .map(|n| start + n)
+                    .ok_or_else(|| self.error("Unterminated text field"))?;
+                self.i = end + 2;
+                let end = match bytes[end - 1] {
+                    b'\r' if end - 1 > start => end - 1,
+                    _ => end,
+                };
+                return Ok(Token::Value(self.span(start + 1, end)));
+            }
+
+            // Quoted values end at a matching quote that's followed by whitespace, so they
+            // can contain that quote, like 'O5'' ' or "N"-methyl". They can't span lines.
+            if bytes[start] == b'"' || bytes[start] == b'\'' {
+                let quote = bytes[start];
+                let mut end = start + 1;
+                loop {
+                    match bytes.get(end) {
+                        Some(b'\n') | None => {
+                            return Err(self.error("Unterminated quoted value"));
+                        }
+                        Some(c) if *c == quote => {
+                            let next = bytes.get(end + 1);
+                            if next.is_none_or(|c| c.is_ascii_whitespace()) {
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                    end += 1;
+                }
+                self.i = end + 1;
+                return Ok(Token::Value(self.span(start + 1, end)));
+            }
+
+            while self.i < bytes.len() && !bytes[self.i].is_ascii_whitespace() {
+                self.i += 1;
+            }
+            let word = &bytes[start..self.i];
+
+            return Ok(match word {
+                b"?" => Token::Value(Span::UNKNOWN),
+                b"." => Token::Value(Span::INAPPLICABLE),
+                _ if word.eq_ignore_ascii_case(b"loop_") => Token::TableStart,
+                _ if word.eq_ignore_ascii_case(b"save_") => Token::SaveEnd,
+                _ if starts_with_keyword(word, b"save_") => Token::SaveStart,
+                _ if [b"data_".as_slice(), b"global_", b"stop_"]
+                    .iter()
+                    .any(|keyword| starts_with_keyword(word, keyword)) =>
+                {
+                    let word = String::from_utf8_lossy(word);
+                    return Err(self.error(format!("Unexpected reserved word {word}")));
+                }
+                [b'_', name @ ..] => Token::Label(
+                    std::str::from_utf8(name).map_err(|_| self.error("Invalid data name"))?,
+                ),
+                _ => Token::Value(self.span(start, self.i)),
+            });
         }
 
-        Token::Value(data.to_string())
+        self.start = self.i;
+        Ok(Token::Eof)
     }
 }
 
+// Line and column of a byte offset, for error messages
+fn location(bytes: &[u8], offset: usize) -> (usize, usize) {
+    let before = &bytes[..offset.min(bytes.len())];
+    let line_start = memrchr(b'\n', before).map_or(0, |i| i + 1);
+    (
+        memchr_iter(b'\n', before).count() + 1,
+        before.len() - line_start + 1,
+    )
+}
+
+// The values of a table, row by row
 #[derive(Default, Debug)]
-struct Table {
-    // keys are sorted by insertion order
-    columns: IndexMap<String, Vec<Token>>,
+struct TableData {
+    name: String,         // category name, as written in the file
+    columns: Vec<String>, // attribute names, in file order
+    values: Vec<Span>,
     num_rows: usize,
 }
 
-impl Table {
-    fn string(&self, column: &str, i: usize) -> Result<String, String> {
-        match &self.columns[column][i] {
-            Token::Value(s) => Ok(s.to_string()),
-            _ => Err("Unexpected token".to_string()),
+// Parse the tables of the data block or global block between the offsets. Categories are
+// case insensitive, so they're stored in lower case.
+fn parse_tables(
+    file: &[u8],
+    start: usize,
+    end: usize,
+) -> Result<HashMap<String, TableData>, ParseError> {
+    let mut tables: HashMap<String, TableData> = HashMap::new();
+    let mut lexer = Lexer {
+        file,
+        bytes: &file[start..end],
+        offset: start,
+        i: 0,
+        start: 0,
+    };
+    if end - start >= Span::INAPPLICABLE.start as usize {
+        return Err(lexer.error("Data block is too large"));
+    }
+
+    let mut token = lexer.next_token()?;
+    loop {
+        match token {
+            Token::Eof => break,
+
+            // The labels of every column, followed by the values of every row
+            Token::TableStart => {
+                let loop_start = lexer.start;
+                let mut category = None;
+                let mut table = TableData::default();
+                token = lexer.next_token()?;
+                while let Token::Label(name) = token {
+                    let (name, attribute) = split_label(name);
+                    category.get_or_insert(name);
+                    table.columns.push(attribute.to_string());
+                    token = lexer.next_token()?;
+                }
+                while let Token::Value(span) = token {
+                    table.values.push(span);
+                    token = lexer.next_token()?;
+                }
+
+                let Some(category) = category else {
+                    return Err(lexer.error_at(loop_start, "loop_ without any labels"));
+                };
+                if table.values.is_empty() {
+                    return Err(lexer.error_at(loop_start, format!("{category} has no values")));
+                }
+                if table.values.len() % table.columns.len() != 0 {
+                    let message = format!(
+                        "{category} has {} values, which isn't a multiple of its {} columns",
+                        table.values.len(),
+                        table.columns.len()
+                    );
+                    return Err(lexer.error_at(loop_start, message));
+                }
+                table.name = category.to_string();
+                table.num_rows = table.values.len() / table.columns.len();
+                tables.insert(category.to_ascii_lowercase(), table);
+                continue; // the token after the table is already read
+            }
+
+            // A single value, which makes up a table with one row
+            Token::Label(name) => {
+                let Token::Value(span) = lexer.next_token()? else {
+                    return Err(lexer.error(format!("_{name} has no value")));
+                };
+                let (category, attribute) = split_label(name);
+                let table = tables
+                    .entry(category.to_ascii_lowercase())
+                    .or_insert_with(|| TableData {
+                        name: category.to_string(),
+                        ..Default::default()
+                    });
+                table.columns.push(attribute.to_string());
+                table.values.push(span);
+                table.num_rows = 1;
+            }
+
+            // Save frames hold the definitions of a dictionary rather than data, so
+            // they're skipped. They can't be nested.
+            Token::SaveStart => loop {
+                match lexer.next_token()? {
+                    Token::SaveEnd => break,
+                    Token::SaveStart => return Err(lexer.error("Nested save frame")),
+                    Token::Eof => return Err(lexer.error("Unterminated save frame")),
+                    _ => {}
+                }
+            },
+            Token::SaveEnd => return Err(lexer.error("save_ outside of a save frame")),
+
+            Token::Value(_) => return Err(lexer.error("Value without a data name")),
+        }
+        token = lexer.next_token()?;
+    }
+
+    Ok(tables)
+}
+
+// A table of a parsed data block. Looking up a value decodes it from the file.
+#[derive(Clone, Copy)]
+pub struct Table<'a> {
+    data: &'a TableData,
+    bytes: &'a [u8], // the file
+    offset: usize,   // where the data block starts in the file
+    pub num_rows: usize,
+}
+
+impl<'a> Table<'a> {
+    fn column(&self, column: &str) -> Option<usize> {
+        // Tables only have a few dozen columns, so a search beats hashing the name.
+        // Names are case insensitive, but they're almost always written as in the dictionary.
+        let columns = &self.data.columns;
+        columns
+            .iter()
+            .position(|c| c == column)
+            .or_else(|| columns.iter().position(|c| c.eq_ignore_ascii_case(column)))
+    }
+
+    pub fn has_column(&self, column: &str) -> bool {
+        self.column(column).is_some()
+    }
+
+    fn span(&self, column: &str, i: usize) -> Option<Span> {
+        let index = self.column(column)?;
+        self.data
+            .values
+            .get(i * self.data.columns.len() + index)
+            .copied()
+    }
+
+    // An error in a value of the table. It points to the value, or to the
+    // start of the row if the value is missing or null.
+    pub fn error(&self, column: &str, i: usize, message: impl Into<String>) -> ParseError {
+        let table = Some(self.data.name.as_str()).filter(|name| !name.is_empty());
+        let error = ParseError::new(message).in_field(table, column);
+
+        let row = self
+            .data
+            .values
+            .iter()
+            .skip(i * self.data.columns.len())
+            .copied();
+        let span = self
+            .span(column, i)
+            .into_iter()
+            .chain(row)
+            .find(|s| !s.is_null());
+        match span {
+            Some(span) => {
+                let (line, column) = location(self.bytes, self.offset + span.start as usize);
+                error.at_line(line).at_column(column)
+            }
+            None => error,
         }
     }
 
-    fn f32(&self, column: &str, i: usize) -> Result<f32, String> {
-        match &self.columns[column][i] {
-            Token::Value(s) => s.parse::<f32>().map_err(|_| "Invalid number".to_string()),
-            _ => Err("Unexpected token".to_string()),
+    // The text of a value, or None for the null values `?` and `.`. Errors are left
+    // as messages, since optional values don't need their location.
+    fn value(&self, column: &str, i: usize) -> Result<Option<&'a str>, &'static str> {
+        if !self.has_column(column) {
+            return Err("Missing column");
+        }
+        let span = self.span(column, i).ok_or("Missing value")?;
+        if span.is_null() {
+            return Ok(None);
         }
+        let start = self.offset + span.start as usize;
+        let bytes = &self.bytes[start..start + span.len as usize];
+        std::str::from_utf8(bytes)
+            .map(Some)
+            .map_err(|_| "Invalid text")
+    }
+
+    pub fn string(&self, column: &str, i: usize) -> Result<&'a str, ParseError> {
+        match self.value(column, i) {
+            Ok(Some(value)) => Ok(value),
+            Ok(None) => Err(self.error(column, i, "Missing value")),
+            Err(message) => Err(self.error(column, i, message)),
+        }
+    }
+
+    pub fn label(&self, column: &str, i: usize) -> Result<Label, ParseError> {
+        let value = self.string(column, i)?;
+        value.parse::<Label>().map_err(|e| self.error(column, i, e))
+    }
+
+    pub fn f32(&self, column: &str, i: usize) -> Result<f32, ParseError> {
+        let value = self.string(column, i)?;
+        value
+            .parse::<f32>()
+            .ok()
+            .filter(|v| v.is_finite())
+            .ok_or_else(|| self.error(column, i, format!("Invalid number: {value}")))
+    }
+
+    // For columns that can be left out of the table, or left blank with `?` or `.`
+    pub fn optional<T: std::str::FromStr>(&self, column: &str, i: usize) -> Option<T> {
+        self.value(column, i).ok()??.parse::<T>().ok()
     }
 }
 
 #[derive(Default, Debug)]
 struct DataBlock {
-    tables: HashMap<String, Table>,
+    tables: HashMap<String, TableData>,
     start_offset: usize,
     end_offset: usize,
+    global: Option<usize>, // the global block before this one, which it inherits tables from
+}
+
+// Block names are case insensitive
+fn block_key(name: &str) -> String {
+    name.to_ascii_lowercase()
 }
 
 #[derive(Default)]
 pub struct MMCIFLoader {
-    data_blocks: HashMap<String, DataBlock>,
+    data_blocks: IndexMap<String, DataBlock>, // in file order
+    global_blocks: Vec<DataBlock>,
     mmap: Option<Mmap>,
 }
 
-impl MMCIFLoader {
-    pub fn open_file(&mut self, path: &Path) -> Result<(), String> {
-        let file = File::open(path).map_err(|e| e.to_string())?;
-        self.mmap = Some(unsafe { MmapOptions::new().map(&file).map_err(|e| e.to_string())? });
-        self.scan_datablocks();
+impl MMCIFLoader {
+    pub fn open_file(&mut self, path: &Path) -> Result<(), ParseError> {
+        let open = || -> Result<Mmap, String> {
+            let file = File::open(path).map_err(|e| e.to_string())?;
+            unsafe { MmapOptions::new().map(&file).map_err(|e| e.to_string()) }
+        };
+        self.mmap = Some(open().map_err(|e| ParseError::new(e).in_file(path))?);
+        self.scan_datablocks().map_err(|e| e.in_file(path))
+    }
+
+    // First pass: scan the file for offsets to data blocks. Headers are at the start of a
+    // line, so the file is scanned line by line, skipping over text fields.
+    fn scan_datablocks(&mut self) -> Result<(), ParseError> {
+        let bytes: &[u8] = self.mmap.as_ref().ok_or("No file open")?;
+        self.data_blocks.clear();
+        self.global_blocks.clear();
+
+        // Offset of the header, offset of the block contents, and name (None for a global block)
+        let mut headers: Vec<(usize, usize, Option<&str>)> = Vec::new();
+        let mut in_text_field = false;
+        let mut line_start = 0;
+
+        while line_start < bytes.len() {
+            let line_end =
+                memchr(b'\n', &bytes[line_start..]).map_or(bytes.len(), |n| line_start + n);
+            let line = &bytes[line_start..line_end];
+
+            if line.first() == Some(&b';') {
+                in_text_field = !in_text_field;
+            } else if !in_text_field {
+                let text = line.trim_ascii_start();
+                let offset = line_start + (line.len() - text.len());
+                let header_end = text
+                    .iter()
+                    .position(|c| c.is_ascii_whitespace())
+                    .unwrap_or(text.len());
+
+                if starts_with_keyword(text, b"data_") {
+                    let error = |message: &str| {
+                        let (line, column) = location(bytes, offset);
+                        ParseError::new(message).at_line(line).at_column(column)
+                    };
+                    let name = std::str::from_utf8(&text[5..header_end])
+                        .map_err(|_| error("Invalid data block name"))?;
+                    if name.is_empty() {
+                        return Err(error("Data block without a name"));
+                    }
+                    headers.push((offset, offset + header_end, Some(name)));
+                } else if starts_with_keyword(text, b"global_") && header_end == 7 {
+                    headers.push((offset, offset + header_end, None));
+                }
+            }
+            line_start = line_end + 1;
+        }
+
+        for (k, (_, start, name)) in headers.iter().enumerate() {
+            let block = DataBlock {
+                start_offset: *start,
+                end_offset: headers.get(k + 1).map_or(bytes.len(), |h| h.0),
+                global: self.global_blocks.len().checked_sub(1),
+                tables: HashMap::new(),
+            };
+            match name {
+                Some(name) => {
+                    self.data_blocks.insert(block_key(name), block);
+                }
+                None => self.global_blocks.push(block),
+            }
+        }
+
+        // Global blocks set defaults for the data blocks after them. They're small, so
+        // they're parsed up front.
+        for block in &mut self.global_blocks {
+            block.tables = parse_tables(bytes, block.start_offset, block.end_offset)?;
+        }
         Ok(())
     }
 
-    // First pass: scan the file for offsets to data blocks
-    fn scan_datablocks(&mut self) {
-        let needle = "data_";
-        let bytes: &[u8] = self.mmap.as_ref().unwrap();
+    pub fn parse_block(&mut self, name: Option<&str>) -> Result<(), ParseError> {
+        let bytes: &[u8] = self.mmap.as_ref().ok_or("No file open")?;
+        let block = match name {
+            Some(name) => self
+                .data_blocks
+                .get_mut(&block_key(name))
+                .ok_or_else(|| format!("{name} not found"))?,
+            // Parse the first data block by default
+            None => self
+                .data_blocks
+                .first_mut()
+                .map(|(_, block)| block)
+                .ok_or("No data blocks found")?,
+        };
 
-        let mut offsets: Vec<usize> = find_iter(bytes, needle.as_bytes())
-            .filter(|i| *i - 0 == 0 || bytes[i - 1] == b'\n')
-            .collect();
-        offsets.push(bytes.len());
-
-        for i in 0..offsets.len() - 1 {
-            let start = offsets[i] + needle.len();
-            let end = offsets[i + 1];
-
-            let name_len = memchr(b'\n', &bytes[start..]).unwrap();
-            let name_slice = &bytes[start..start + name_len];
-            let block_name = std::str::from_utf8(name_slice).unwrap();
-
-            self.data_blocks.insert(
-                String::from(block_name),
-                DataBlock {
-                    end_offset: end,
-                    start_offset: start + name_len,
-                    tables: HashMap::new(),
-                },
-            );
-        }
+        block.tables = parse_tables(bytes, block.start_offset, block.end_offset)?;
+        Ok(())
     }
 
-    fn next_token(i: &mut usize, bytes: &[u8], peek: bool) -> Token {
-        let before = *i;
-        let substring = |a: usize, b: usize| std::str::from_utf8(&bytes[a..b]).unwrap();
+    // Bonds and secondary structure of the atoms of one model, sorted by chain and sequence id
+    fn parse_model(
+        &self,
+        atoms: &[Atom],
+        diagnostics: &mut Diagnostics,
+    ) -> Result<(Vec<Bond>, Vec<SecondaryStructure>), ParseError> {
+        let hierarchy = Hierarchy::new(atoms, std::iter::once(0..atoms.len()));
+        // Atoms of a residue, for the ranges of secondary structure
+        let residue = |comp_id: &str, chain_id: &str, seq_id: Option<SeqId>| {
+            let residues = hierarchy.find_residues(0, chain_id, seq_id?);
+            let residue = residues.iter().find(|r| r.name == comp_id);
+            residue.map(|r| r.atoms.clone())
+        };
 
-        while *i < bytes.len() {
-            // Ignore comments
-            if bytes[*i] == b'#' {
-                while *i < bytes.len() && bytes[*i] != b'\n' {
-                    *i += 1;
-                }
-                continue;
+        // Parse bonds
+        let mut component_bonds: HashMap<&str, Vec<(Label, Label, BondType)>> = HashMap::new();
+        if let Ok(t) = self.get_table(None, "chem_comp_bond") {
+            for i in 0..t.num_rows {
+                component_bonds
+                    .entry(t.string("comp_id", i)?)
+                    .or_default()
+                    .push((
+                        t.label("atom_id_1", i)?,
+                        t.label("atom_id_2", i)?,
+                        chem_comp_bond_type(&t, i)?,
+                    ));
             }
+        }
 
-            // Ignore whitespace
-            if bytes[*i].is_ascii_whitespace() {
-                *i += 1;
+        // The component might only be in some of the models
+        let mut bonds: Vec<Bond> = Vec::new();
+        let mut component_atoms: IndexMap<&str, HashSet<Label>> = IndexMap::new();
+        for residue in &hierarchy.residues {
+            let Some(residue_bonds) = component_bonds.get(residue.name) else {
                 continue;
+            };
+            let mut by_id: HashMap<Label, Vec<usize>> = HashMap::new();
+            for i in residue.atoms.clone() {
+                by_id.entry(atoms[i].atom_id).or_default().push(i);
             }
+            let seen = component_atoms.entry(residue.name).or_default();
+            seen.extend(by_id.keys().copied());
 
-            let start = *i;
+            for (src_id, dst_id, bond_type) in residue_bonds {
+                let (Some(srcs), Some(dsts)) = (by_id.get(src_id), by_id.get(dst_id)) else {
+                    continue;
+                };
+                for (&src, &dst) in srcs.iter().cartesian_product(dsts) {
+                    if atoms[src].same_conformer(&atoms[dst]) {
+                        bonds.push(Bond {
+                            src,
+                            dst,
+                            bond_type: *bond_type,
+                        });
+                    }
+                }
+            }
+        }
 
-            if bytes[*i] == b'"' || bytes[*i] == b'\'' || bytes[*i] == b';' {
-                // Start quote
-                let quote = bytes[*i];
-                *i += 1;
+        // Atoms that aren't in any residue of the component, which is often every
+        // hydrogen of an X-ray structure
+        for (comp_id, seen) in &component_atoms {
+            let missing: Vec<&str> = component_bonds[comp_id]
+                .iter()
+                .flat_map(|(src, dst, _)| [src, dst])
+                .filter(|id| !seen.contains(id))
+                .map(|id| id.as_str())
+                .unique()
+                .collect();
+            if !missing.is_empty() {
+                diagnostics.warn(format!(
+                    "chem_comp_bond of {comp_id} references missing atoms: {}",
+                    missing.join(", ")
+                ));
+            }
+        }
 
-                while *i < bytes.len() && bytes[*i] != quote {
-                    *i += 1;
-                }
+        bonds.append(&mut polymer_links(&hierarchy));
 
-                *i += 1; // End quote
-                let token = Token::new(substring(start + 1, *i - 1));
-                if peek {
-                    *i = before;
-                }
-                return token;
-            } else {
-                while *i < bytes.len() && !bytes[*i].is_ascii_whitespace() && bytes[*i] != b'#' {
-                    *i += 1;
+        // Links that aren't part of the polymer backbone, like disulfide bridges,
+        // metal coordination and covalently attached ligands
+        if let Ok(t) = self.get_table(None, "struct_conn") {
+            for i in 0..t.num_rows {
+                // A link to a symmetry mate would be drawn across the structure
+                let symmetry1 = t.optional::<String>("ptnr1_symmetry", i);
+                if symmetry1 != t.optional::<String>("ptnr2_symmetry", i) {
+                    continue;
                 }
 
-                let token = Token::new(substring(start, *i));
-                if peek {
-                    *i = before;
+                let bond_type = match t.string("conn_type_id", i)?.to_lowercase().as_str() {
+                    "disulf" => BondType::Disulfide,
+                    "metalc" => BondType::MetalCoordination,
+                    "hydrog" => BondType::HBond,
+                    c if c.starts_with("covale") => {
+                        match t.optional::<String>("pdbx_value_order", i) {
+                            Some(order) => value_order(&order)
+                                .map_err(|e| t.error("pdbx_value_order", i, e))?,
+                            None => BondType::Single,
+                        }
+                    }
+                    _ => continue, // mismatched base pairs
+                };
+
+                let partner = |n: usize| -> Result<Option<usize>, ParseError> {
+                    let component = t.label(&format!("ptnr{n}_label_comp_id"), i)?;
+                    let chain = t.string(&format!("ptnr{n}_label_asym_id"), i)?;
+                    let atom = t.label(&format!("ptnr{n}_label_atom_id"), i)?;
+                    let seq = t
+                        .optional::<SeqId>(&format!("ptnr{n}_label_seq_id"), i)
+                        .or_else(|| t.optional(&format!("ptnr{n}_auth_seq_id"), i));
+                    let Some(seq) = seq else {
+                        return Ok(None);
+                    };
+                    // Links to atoms with alternate locations are listed once per location
+                    let alt_id = t.optional::<char>(&format!("pdbx_ptnr{n}_label_alt_id"), i);
+                    let indexes: Vec<usize> = hierarchy
+                        .find_atoms(0, chain, seq, atom)
+                        .filter(|j| atoms[*j].component_name == component)
+                        .collect();
+                    let matching = indexes.iter().find(|j| atoms[**j].alt_id == alt_id);
+                    Ok(matching.or(indexes.first()).copied())
+                };
+
+                if let (Some(src), Some(dst)) = (partner(1)?, partner(2)?) {
+                    bonds.push(Bond {
+                        src,
+                        dst,
+                        bond_type,
+                    });
                 }
-                return token;
             }
         }
 
-        if peek {
-            *i = before;
-        }
-        Token::Eof
-    }
-
-    fn parse_block(&mut self, name: Option<&str>) -> Result<(), String> {
-        let key = match name {
-            Some(s) => s,
-            // Parse the first data block by default
-            None => &self
-                .data_blocks
-                .keys()
-                .next()
-                .ok_or("No data blocks found")?
-                .clone(),
-        };
+        if let Ok(t) = self.get_table(None, "pdbx_struct_sheet_hbond") {
+            for i in 0..t.num_rows {
+                let chain1 = t.string("range_1_label_asym_id", i)?;
+                let atom1 = t.label("range_1_label_atom_id", i)?;
+                let chain2 = t.string("range_2_label_asym_id", i)?;
+                let atom2 = t.label("range_2_label_atom_id", i)?;
+                let seq1 = t.optional::<SeqId>("range_1_label_seq_id", i);
+                let seq2 = t.optional::<SeqId>("range_2_label_seq_id", i);
+                let (Some(seq1), Some(seq2)) = (seq1, seq2) else {
+                    continue;
+                };
 
-        let block = self
-            .data_blocks
-            .get_mut(key)
-            .ok_or_else(|| format!("{} not found", key))?;
-        let (mut i, end) = (block.start_offset, block.end_offset);
-
-        let mut in_table = false;
-        let mut prev_column = String::new();
-        let mut prev_block = String::new();
-
-        let bytes = self.mmap.as_ref().unwrap();
-        while i < end {
-            let token = Self::next_token(&mut i, bytes, false);
-
-            match token {
-                Token::TableStart | Token::Eof => in_table = true,
-                Token::Label((block_name, column)) => {
-                    prev_column = column.clone();
-                    prev_block = block_name.clone();
-                    block
-                        .tables
-                        .entry(block_name)
-                        .or_default()
-                        .columns
-                        .insert(column, Vec::new());
+                let src = hierarchy.find_atom(0, chain1, seq1, atom1);
+                let dst = hierarchy.find_atom(0, chain2, seq2, atom2);
+                if let (Some(src), Some(dst)) = (src, dst) {
+                    bonds.push(Bond {
+                        src,
+                        dst,
+                        bond_type: BondType::HBond,
+                    });
                 }
-                Token::Value(_) => {
-                    // Dictionary value
-                    if !in_table {
-                        let table = block.tables.entry(prev_block.clone()).or_default();
-                        table.columns.get_mut(&prev_column).unwrap().push(token);
-                        table.num_rows = 1;
-                        continue;
-                    }
+            }
+        }
 
-                    // Table row
-                    // Remember that this works because keys are sorted by their insertion order
-                    let table = block.tables.entry(prev_block.clone()).or_default();
-                    let keys: Vec<String> = table.columns.keys().cloned().collect();
-                    table.num_rows += 1;
+        let mut secondary: Vec<SecondaryStructure> = Vec::new();
 
-                    for key_idx in 0..keys.len() {
-                        let t = if key_idx == 0 {
-                            token.clone()
-                        } else {
-                            Self::next_token(&mut i, bytes, false)
-                        };
-                        table
-                            .columns
-                            .entry(keys[key_idx].clone())
-                            .or_default()
-                            .push(t);
+        // Parse helixes, and the turns and bends of files from DSSP
+        if let Ok(t) = self.get_table(None, "struct_conf") {
+            for i in 0..t.num_rows {
+                let helix_class = t.optional::<u8>("pdbx_PDB_helix_class", i);
+                let conf_type_id = t.string("conf_type_id", i)?;
+                let Some(struct_type) = conf_type(conf_type_id, helix_class) else {
+                    let message = format!("Unknown secondary structure type {conf_type_id}");
+                    diagnostics.warn(t.error("conf_type_id", i, message).to_string());
+                    continue;
+                };
+
+                let comp_start = t.string("beg_label_comp_id", i)?;
+                let chain_start = t.string("beg_label_asym_id", i)?;
+                let seq_start = t.optional("beg_label_seq_id", i);
+                let comp_end = t.string("end_label_comp_id", i)?;
+                let chain_end = t.string("end_label_asym_id", i)?;
+                let seq_end = t.optional("end_label_seq_id", i);
+                let start = residue(comp_start, chain_start, seq_start);
+                let end = residue(comp_end, chain_end, seq_end);
+                let (Some(start), Some(end)) = (start.clone(), end) else {
+                    let field = match start {
+                        Some(_) => "end_label_seq_id",
+                        None => "beg_label_seq_id",
+                    };
+                    let error = t.error(field, i, "Range refers to a missing residue");
+                    diagnostics.warn(error.to_string());
+                    continue;
+                };
+                let (start, end) = match secondary_atoms((chain_start, start), (chain_end, end)) {
+                    Ok(range) => range,
+                    Err(message) => {
+                        diagnostics.warn(t.error("end_label_seq_id", i, message).to_string());
+                        continue;
                     }
+                };
+                secondary.push(SecondaryStructure {
+                    struct_type,
+                    start,
+                    end,
+                    sheet_id: None,
+                });
+            }
+        }
 
-                    // Is last row?
-                    let next_is_value =
-                        matches!(Self::next_token(&mut i, bytes, true), Token::Value(_));
-                    if in_table && !next_is_value {
-                        in_table = false;
+        // Parse sheets
+        if let Ok(t) = self.get_table(None, "struct_sheet_range") {
+            for i in 0..t.num_rows {
+                let comp_start = t.string("beg_label_comp_id", i)?;
+                let chain_start = t.string("beg_label_asym_id", i)?;
+                let seq_start = t.optional("beg_label_seq_id", i);
+                let comp_end = t.string("end_label_comp_id", i)?;
+                let chain_end = t.string("end_label_asym_id", i)?;
+                let seq_end = t.optional("end_label_seq_id", i);
+                let start = residue(comp_start, chain_start, seq_start);
+                let end = residue(comp_end, chain_end, seq_end);
+                let (Some(start), Some(end)) = (start.clone(), end) else {
+                    let field = match start {
+                        Some(_) => "end_label_seq_id",
+                        None => "beg_label_seq_id",
+                    };
+                    let error = t.error(field, i, "Strand refers to a missing residue");
+                    diagnostics.warn(error.to_string());
+                    continue;
+                };
+                let (start, end) = match secondary_atoms((chain_start, start), (chain_end, end)) {
+                    Ok(range) => range,
+                    Err(message) => {
+                        diagnostics.warn(t.error("end_label_seq_id", i, message).to_string());
+                        continue;
                     }
-                }
+                };
+                secondary.push(SecondaryStructure {
+                    struct_type: SecondaryType::BetaSheet,
+                    start,
+                    end,
+                    sheet_id: t.optional("sheet_id", i),
+                });
             }
         }
 
-        Ok(())
+        Ok((bonds, secondary))
     }
 
-    fn get_table(&self, block_id: Option<&str>, table_id: &str) -> Result<&Table, String> {
-        let key = match block_id {
+    pub fn get_table(
+        &self,
+        block_id: Option<&str>,
+        table_id: &str,
+    ) -> Result<Table<'_>, ParseError> {
+        let (name, block) = match block_id {
             // In most files, there will only be one datablock,
             // so it can be used as the default datablock
-            None => self
+            None => self.data_blocks.first().ok_or("File contains no blocks")?,
+            Some(b_id) => self
                 .data_blocks
-                .keys()
-                .next()
-                .ok_or(String::from("File contains no blocks"))?,
-            Some(b_id) => b_id,
+                .get_key_value(&block_key(b_id))
+                .ok_or_else(|| format!("{b_id} not found"))?,
         };
 
-        self.data_blocks[key]
-            .tables
-            .get(table_id)
-            .ok_or(format!("{table_id} not found in {key}"))
+        // Tables that aren't in the block can be inherited from a global block
+        let category = table_id.to_ascii_lowercase();
+        let global = block.global.and_then(|g| self.global_blocks.get(g));
+        let (block, data) = std::iter::once(block)
+            .chain(global)
+            .find_map(|b| b.tables.get(&category).map(|data| (b, data)))
+            .ok_or_else(|| format!("{table_id} not found in {name}"))?;
+
+        let mmap = self.mmap.as_ref().ok_or("No file open")?;
+        Ok(Table {
+            data,
+            bytes: mmap,
+            offset: block.start_offset,
+            num_rows: data.num_rows,
+        })
+    }
+}
+
+// Expand an operator list like "1-5,11,P" into the operator ids.
+// Ranges only make sense for numeric ids, anything else is taken as is. A range
+// can't be longer than the list of operators, which keeps a corrupt one like
+// "1-999999999" from using up all of the memory.
+fn operator_ids(list: &str, num_operators: usize) -> Result<Vec<String>, String> {
+    let mut ids = Vec::new();
+    for item in list.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        let range = item
+            .split_once('-')
+            .and_then(|(a, b)| Some((a.parse::<usize>().ok()?, b.parse::<usize>().ok()?)));
+        match range {
+            Some((first, last)) if last.saturating_sub(first) < num_operators => {
+                ids.extend((first..=last).map(|id| id.to_string()))
+            }
+            Some(_) => return Err(format!("Invalid operator range {item}")),
+            None => ids.push(item.to_string()),
+        }
     }
+    Ok(ids)
 }
 
 // Generates all (chain, transform) pairs needed to build a biological assembly
 // from an mmCIF `oper_expression` and `asym_id_list`.
 //
 // The oper_expression describes which operations from `pdbx_struct_oper_list` to apply,
-// in one of three forms:
-//   - "1"         → single operation
-//   - "1,2,3"     → each operation independently (one copy of chains per op)
-//   - "(1,2)(3,4)"→ Cartesian product: one copy per combination (1×3, 1×4, 2×3, 2×4)
+// in one of these forms:
+//   - "1"          → single operation
+//   - "1,2,3"      → each operation independently (one copy of chains per op)
+//   - "(1-60)"     → ranges are expanded into the full list of operation IDs
+//   - "(1,2)(3,4)" → Cartesian product: one copy per combination (1×3, 1×4, 2×3, 2×4)
 //
-// Ranges like "(1-60)" are expanded into the full list of operation IDs.
+// Operation IDs don't have to be numbers, like "P" or "X0".
 // For product expressions, each combination is multiplied into a single matrix.
 // Every resulting matrix is paired with every chain in `chains`.
 fn generate_chain_copies(
     expression: &str,
-    chains: &Vec<String>,
-    transforms: &HashMap<usize, Mat4>,
-) -> Vec<(String, Mat4)> {
-    let is_sequence = !expression.starts_with("(");
-
-    let groups = if is_sequence {
-        // Sequence form "1,2,3": wrap each ID in its own group so the
-        // Cartesian product below treats them as independent operations.
-        expression
-            .split(',')
-            .map(|s| vec![s.parse::<usize>().unwrap()])
-            .collect()
-    } else {
-        // Parenthesized form "(1,2)(3-5)": each (...) is one group.
-        // Strip the leading "(" (+1) and stop before the closing ")".
-        // Ranges like "3-5" are expanded to [3, 4, 5].
-        let mut i = 0;
-        let mut lists = Vec::new();
-
-        while i < expression.len() {
-            let end = i + expression[i..].find(')').unwrap();
-            let group = &expression[i + 1..end];
-
-            let is_range = group.contains("-");
-            let values: Vec<usize> = group
-                .split(if is_range { "-" } else { "," })
-                .map(|s| s.parse::<usize>().unwrap())
-                .collect();
-
-            lists.push(if is_range {
-                (values[0]..=values[1]).collect::<Vec<usize>>()
-            } else {
-                values
-            });
-            i = end + 1;
-        }
-
-        lists
-    };
+    chains: &[String],
+    transforms: &HashMap<String, Mat4>,
+) -> Result<Vec<(String, Mat4)>, String> {
+    // Each (...) is one group, and a list outside of parentheses is a group of its own
+    let mut groups: Vec<Vec<String>> = Vec::new();
+    let mut rest = expression.trim();
+    while !rest.is_empty() {
+        let (group, remaining) = match rest.strip_prefix('(') {
+            Some(inner) => {
+                let end = inner
+                    .find(')')
+                    .ok_or(format!("Unbalanced parentheses in {expression}"))?;
+                (&inner[..end], &inner[end + 1..])
+            }
+            None => rest.split_at(rest.find('(').unwrap_or(rest.len())),
+        };
+        groups.push(operator_ids(group, transforms.len())?);
+        rest = remaining.trim();
+    }
 
     // Build the Cartesian product across all groups, then fold each
     // combination into a single matrix via left-to-right multiplication.
-    // A sequence "1,2,3" has groups [[1],[2],[3]] so no multiplication occurs.
+    // A sequence "1,2,3" is a single group, so no multiplication occurs.
     let mut chain_copies: Vec<(String, Mat4)> = Vec::new();
 
     for combo in groups.into_iter().multi_cartesian_product() {
         let mut result = Mat4::IDENTITY;
         for id in &combo {
-            result *= transforms[id]
+            result *= *transforms
+                .get(id)
+                .ok_or(format!("Unknown assembly operation {id}"))?;
         }
 
         for chain in chains {
@@ -403,238 +2040,743 @@ fn generate_chain_copies(
         }
     }
 
-    chain_copies
+    Ok(chain_copies)
 }
 
-#[derive(Default, Debug)]
-struct Strand {
-    seq_offset: usize,
-    atoms: HashMap<String, usize>, // atom id to indexes
+// Bonds between consecutive residues of each polymer chain: peptide bonds in proteins and
+// phosphodiester bonds in nucleic acids. `chem_comp_bond` only has the bonds within a residue,
+// so without these every chain would be drawn as disconnected residues.
+fn polymer_links(hierarchy: &Hierarchy) -> Vec<Bond> {
+    // Atom ids in the (previous, next) residue
+    let linkages = [("C", "N"), ("O3'", "P")];
+    // Linked atoms that are further apart than this are on either side of a gap in the model
+    let max_distance = 2.0;
+    let atoms = hierarchy.atoms;
+
+    let mut bonds: Vec<Bond> = Vec::new();
+    for chain in &hierarchy.chains {
+        // Alternate residues at the same position are linked to both neighbours. Numbering
+        // can skip or have insertion codes, so the residues are linked in the order they're
+        // in, and the distance check leaves out the ones on either side of a gap.
+        let residues = hierarchy.residues(chain);
+        let positions = residues.chunk_by(|a, b| a.seq_id == b.seq_id);
+        for (prev, next) in positions.tuple_windows() {
+            let with_id = |residues: &[Residue], atom_id: &str| -> Vec<usize> {
+                let indexes = residues.iter().flat_map(|r| r.atoms.clone());
+                indexes.filter(|i| atoms[*i].atom_id == atom_id).collect()
+            };
+            for (src_id, dst_id) in linkages {
+                let (srcs, dsts) = (with_id(prev, src_id), with_id(next, dst_id));
+                for (&src, &dst) in srcs.iter().cartesian_product(&dsts) {
+                    let (src_atom, dst_atom) = (&atoms[src], &atoms[dst]);
+                    if src_atom.same_conformer(dst_atom)
+                        && src_atom.position.distance(dst_atom.position) < max_distance
+                    {
+                        bonds.push(Bond {
+                            src,
+                            dst,
+                            bond_type: BondType::Single,
+                        });
+                    }
+                }
+            }
+        }
+    }
+    bonds
 }
 
-// (chain_id, seq_id) to strand
-type Component = HashMap<(String, String), Strand>;
+// Bond order from the `value_order` of chem_comp_bond and struct_conn
+fn value_order(order: &str) -> Result<BondType, String> {
+    match order.to_lowercase().as_str() {
+        "sing" | "poly" => Ok(BondType::Single),
+        "doub" => Ok(BondType::Double),
+        "trip" => Ok(BondType::Triple),
+        "quad" => Ok(BondType::Quadruple),
+        "arom" => Ok(BondType::Aromatic),
+        "delo" | "pi" => Ok(BondType::Delocalized),
+        x => Err(format!("Unkonwn bond type {x}")),
+    }
+}
 
-impl FileLoader for MMCIFLoader {
-    fn parse_file(&mut self, path: &Path) -> Result<Structure, String> {
+// Bond order of a row in a `chem_comp_bond` table
+pub fn chem_comp_bond_type(t: &Table, i: usize) -> Result<BondType, ParseError> {
+    // Aromatic bonds are usually written as alternating single and double bonds
+    if t.optional::<String>("pdbx_aromatic_flag", i).as_deref() == Some("Y") {
+        return Ok(BondType::Aromatic);
+    }
+    value_order(t.string("value_order", i)?).map_err(|e| t.error("value_order", i, e))
+}
+
+impl MMCIFLoader {
+    fn parse_structures(&mut self, path: &Path) -> Result<Vec<Structure>, ParseError> {
         self.open_file(path)?;
         self.parse_block(None)?;
 
         let mut atoms: Vec<Atom> = Vec::new();
+        let mut models: Vec<usize> = Vec::new();
+        let mut diagnostics = Diagnostics::default();
 
         // Parse atoms
         if let Ok(t) = self.get_table(None, "chem_comp_atom") {
             for i in 0..t.num_rows {
-                if !t.columns.contains_key("pdbx_model_Cartn_x_ideal") {
+                if !t.has_column("pdbx_model_Cartn_x_ideal") {
                     break;
                 }
                 atoms.push(Atom {
-                    chain_id: String::new(),
-                    sequence_id: String::new(),
-                    component_name: t.string("comp_id", i)?,
-                    atom_id: t.string("atom_id", i)?,
-                    element: t.string("type_symbol", i)?,
+                    component_name: t.label("comp_id", i)?,
+                    atom_id: t.label("atom_id", i)?,
+                    element: element(t.string("type_symbol", i)?, &mut diagnostics),
                     is_ligand: true,
                     position: glam::Vec3::new(
                         t.f32("pdbx_model_Cartn_x_ideal", i)?,
                         t.f32("pdbx_model_Cartn_y_ideal", i)?,
                         t.f32("pdbx_model_Cartn_z_ideal", i)?,
                     ),
+                    charge: t.optional::<i8>("charge", i).unwrap_or(0),
+                    ..Default::default()
                 });
+                models.push(1);
             }
         }
 
         if let Ok(t) = self.get_table(None, "atom_site") {
             for i in 0..t.num_rows {
+                // Non polymer residues have no sequence id, so they're numbered
+                // by their author sequence id instead
+                let sequence_id = t
+                    .optional("label_seq_id", i)
+                    .or_else(|| t.optional("auth_seq_id", i))
+                    .unwrap_or_default();
                 atoms.push(Atom {
-                    chain_id: t.string("label_asym_id", i)?,
-                    sequence_id: t.string("label_seq_id", i)?,
-                    component_name: t.string("label_comp_id", i)?,
-                    atom_id: t.string("label_atom_id", i)?,
-                    element: t.string("type_symbol", i)?,
+                    chain_id: t.label("label_asym_id", i)?,
+                    sequence_id,
+                    component_name: t.label("label_comp_id", i)?,
+                    atom_id: t.label("label_atom_id", i)?,
+                    element: element(t.string("type_symbol", i)?, &mut diagnostics),
                     is_ligand: t.string("group_PDB", i)? == "HETATM",
                     position: glam::Vec3::new(
                         t.f32("Cartn_x", i)?,
                         t.f32("Cartn_y", i)?,
                         t.f32("Cartn_z", i)?,
                     ),
+                    charge: t.optional::<i8>("pdbx_formal_charge", i).unwrap_or(0),
+                    alt_id: t.optional::<char>("label_alt_id", i),
+                    occupancy: t.optional("occupancy", i),
+                    b_factor: t.optional("B_iso_or_equiv", i),
+                    ..Default::default()
                 });
+                models.push(t.optional("pdbx_PDB_model_num", i).unwrap_or(1));
             }
         }
 
-        // Sort atoms by chain, sequence id and component name
-        atoms.sort_by(|a: &Atom, b: &Atom| {
-            // Ensure that sequences are sorted in ascending order, not lexographic order
-            let s_a = a.sequence_id.parse::<i32>().unwrap_or(0);
-            let s_b = b.sequence_id.parse::<i32>().unwrap_or(0);
-            a.chain_id
-                .cmp(&b.chain_id)
-                .then(s_a.cmp(&s_b))
-                .then(a.component_name.cmp(&b.component_name))
-        });
-
-        // Group atom indexes by component, then by chain id and sequence id
-        let mut components: HashMap<String, Component> = HashMap::new();
-        let mut prev_chain_id = String::new();
-        let mut prev_seq_id = String::new();
-
-        for (index, atom) in atoms.iter().enumerate() {
-            let current = components
-                .entry(atom.component_name.clone())
-                .or_default()
-                .entry((atom.chain_id.clone(), atom.sequence_id.clone()))
-                .or_default();
-
-            // sequence changed
-            if atom.sequence_id.clone() != prev_seq_id || atom.chain_id.clone() != prev_chain_id {
-                current.seq_offset = index;
-            }
-            current.atoms.insert(atom.atom_id.clone(), index);
-
-            prev_seq_id = atom.sequence_id.clone();
-            prev_chain_id = atom.chain_id.clone();
-        }
-
-        // Parse bonds
-        let mut bonds: Vec<Bond> = Vec::new();
-        if let Ok(t) = self.get_table(None, "chem_comp_bond") {
-            for i in 0..t.num_rows {
-                let component_id = t.string("comp_id", i)?;
-                let src_id = t.string("atom_id_1", i)?;
-                let dst_id = t.string("atom_id_2", i)?;
-                let bond_type = match t.string("value_order", i)?.to_lowercase().as_str() {
-                    "sing" => BondType::Single,
-                    "doub" => BondType::Double,
-                    "trip" => BondType::Triple,
-                    x => return Err(format!("Unkonwn bond type {x}")),
-                };
-
-                for instance in components[&component_id].values() {
-                    if !instance.atoms.contains_key(&src_id)
-                        || !instance.atoms.contains_key(&dst_id)
-                    {
-                        continue;
-                    }
-                    bonds.push(Bond {
-                        src: instance.atoms[&src_id],
-                        dst: instance.atoms[&dst_id],
-                        bond_type,
-                    });
-                }
-            }
-        }
-
-        if let Ok(t) = self.get_table(None, "pdbx_struct_sheet_hbond") {
-            for i in 0..t.num_rows {
-                let component1 = t.string("range_1_label_comp_id", i)?;
-                let chain1 = t.string("range_1_label_asym_id", i)?;
-                let seq1 = t.string("range_1_label_seq_id", i)?;
-                let atom1 = t.string("range_1_label_atom_id", i)?;
-
-                let component2 = t.string("range_2_label_comp_id", i)?;
-                let chain2 = t.string("range_2_label_asym_id", i)?;
-                let seq2 = t.string("range_2_label_seq_id", i)?;
-                let atom2 = t.string("range_2_label_atom_id", i)?;
-
-                bonds.push(Bond {
-                    src: components[&component1][&(chain1, seq1)].atoms[&atom1],
-                    dst: components[&component2][&(chain2, seq2)].atoms[&atom2],
-                    bond_type: BondType::HBond,
+        // Sort atoms by model, chain, sequence id and component name
+        let mut rows: Vec<(usize, Atom)> = models.into_iter().zip(atoms).collect();
+        rows.sort_by_key(|(model, a)| (*model, a.chain_id, a.sequence_id, a.component_name));
+
+        // Each model (like the conformers of an NMR ensemble) is a frame
+        let mut atoms: Vec<Atom> = Vec::with_capacity(rows.len());
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut prev_model = None;
+
+        for (model, atom) in rows {
+            if prev_model != Some(model) {
+                frames.push(Frame {
+                    atoms: atoms.len()..atoms.len(),
+                    properties: IndexMap::from([(String::from("Model"), model.to_string())]),
+                    ..Default::default()
                 });
+                prev_model = Some(model);
+            }
+            atoms.push(atom);
+            if let Some(frame) = frames.last_mut() {
+                frame.atoms.end = atoms.len();
             }
         }
 
+        let mut bonds: Vec<Bond> = Vec::new();
         let mut secondary: Vec<SecondaryStructure> = Vec::new();
 
-        // Parse helixes
-        if let Ok(t) = self.get_table(None, "struct_conf") {
-            for i in 0..t.num_rows {
-                let comp_start = t.string("beg_label_comp_id", i)?;
-                let chain_start = t.string("beg_label_asym_id", i)?;
-                let seq_start = t.string("beg_label_seq_id", i)?;
-                let comp_end = t.string("end_label_comp_id", i)?;
-                let chain_end = t.string("end_label_asym_id", i)?;
-                let seq_end = t.string("end_label_seq_id", i)?;
-                secondary.push(SecondaryStructure {
-                    struct_type: match t.string("conf_type_id", i)?.as_str() {
-                        _ => SecondaryType::AlphaHelix, // FIXME!
-                    },
-                    start: components[&comp_start][&(chain_start, seq_start)].seq_offset,
-                    end: components[&comp_end][&(chain_end, seq_end)].seq_offset,
-                });
-            }
+        // Bonds and secondary structure are the same for every model, but they
+        // have to point to the atoms of each one
+        for frame in &frames {
+            let range = frame.atoms.clone();
+            let (model_bonds, model_secondary) =
+                self.parse_model(&atoms[range.clone()], &mut diagnostics)?;
+
+            bonds.extend(model_bonds.into_iter().map(|b| Bond {
+                src: b.src + range.start,
+                dst: b.dst + range.start,
+                ..b
+            }));
+            secondary.extend(model_secondary.into_iter().map(|s| SecondaryStructure {
+                start: s.start + range.start,
+                end: s.end + range.start,
+                ..s
+            }));
         }
 
-        // Parse sheets
-        if let Ok(t) = self.get_table(None, "struct_sheet_range") {
-            for i in 0..t.num_rows {
-                let comp_start = t.string("beg_label_comp_id", i)?;
-                let chain_start = t.string("beg_label_asym_id", i)?;
-                let seq_start = t.string("beg_label_seq_id", i)?;
-                let comp_end = t.string("end_label_comp_id", i)?;
-                let chain_end = t.string("end_label_asym_id", i)?;
-                let seq_end = t.string("end_label_seq_id", i)?;
-                secondary.push(SecondaryStructure {
-                    struct_type: SecondaryType::BetaSheet,
-                    start: components[&comp_start][&(chain_start, seq_start)].seq_offset,
-                    end: components[&comp_end][&(chain_end, seq_end)].seq_offset,
-                });
-            }
+        // A single model doesn't need to be split into frames
+        if frames.len() == 1 {
+            frames.clear();
         }
 
         // Parse assemblies
-        let mut transforms: HashMap<usize, Mat4> = HashMap::new();
+        let mut transforms: HashMap<String, Mat4> = HashMap::new();
 
         if let Ok(t) = self.get_table(None, "pdbx_struct_oper_list") {
             for i in 0..t.num_rows {
-                *transforms.entry(t.f32("id", i)? as usize).or_default() = Mat4::from_cols(
-                    Vec4::new(
-                        t.f32("matrix[1][1]", i)?,
-                        t.f32("matrix[2][1]", i)?,
-                        t.f32("matrix[3][1]", i)?,
-                        0.0,
-                    ),
-                    Vec4::new(
-                        t.f32("matrix[1][2]", i)?,
-                        t.f32("matrix[2][2]", i)?,
-                        t.f32("matrix[3][2]", i)?,
-                        0.0,
-                    ),
-                    Vec4::new(
-                        t.f32("matrix[1][3]", i)?,
-                        t.f32("matrix[2][3]", i)?,
-                        t.f32("matrix[3][3]", i)?,
-                        0.0,
-                    ),
-                    Vec4::new(
-                        t.f32("vector[1]", i)?,
-                        t.f32("vector[2]", i)?,
-                        t.f32("vector[3]", i)?,
-                        1.0,
+                transforms.insert(
+                    t.string("id", i)?.to_string(),
+                    Mat4::from_cols(
+                        Vec4::new(
+                            t.f32("matrix[1][1]", i)?,
+                            t.f32("matrix[2][1]", i)?,
+                            t.f32("matrix[3][1]", i)?,
+                            0.0,
+                        ),
+                        Vec4::new(
+                            t.f32("matrix[1][2]", i)?,
+                            t.f32("matrix[2][2]", i)?,
+                            t.f32("matrix[3][2]", i)?,
+                            0.0,
+                        ),
+                        Vec4::new(
+                            t.f32("matrix[1][3]", i)?,
+                            t.f32("matrix[2][3]", i)?,
+                            t.f32("matrix[3][3]", i)?,
+                            0.0,
+                        ),
+                        Vec4::new(
+                            t.f32("vector[1]", i)?,
+                            t.f32("vector[2]", i)?,
+                            t.f32("vector[3]", i)?,
+                            1.0,
+                        ),
                     ),
                 );
             }
         }
 
-        let mut chain_copies: Vec<(String, Mat4)> = Vec::new();
+        let mut assemblies: IndexMap<String, Assembly> = IndexMap::new();
+
+        if let Ok(t) = self.get_table(None, "pdbx_struct_assembly") {
+            for i in 0..t.num_rows {
+                let id = t.string("id", i)?.to_string();
+                assemblies.insert(
+                    id.clone(),
+                    Assembly {
+                        id,
+                        details: t.optional("details", i).unwrap_or_default(),
+                        oligomeric: t.optional("oligomeric_details", i).unwrap_or_default(),
+                        ..Default::default()
+                    },
+                );
+            }
+        }
 
+        // An assembly can be made of several rows, each moving a different set of chains
         if let Ok(t) = self.get_table(None, "pdbx_struct_assembly_gen") {
             for i in 0..t.num_rows {
-                let chains = t
+                let chains: Vec<String> = t
                     .string("asym_id_list", i)?
                     .split(',')
-                    .map(|s| s.to_string())
+                    .map(|s| s.trim().to_string())
                     .collect();
-                chain_copies =
-                    generate_chain_copies(&t.string("oper_expression", i)?, &chains, &transforms);
+                let id = t.string("assembly_id", i)?.to_string();
+                let expression = t.string("oper_expression", i)?;
+                let mut copies = generate_chain_copies(expression, &chains, &transforms)
+                    .map_err(|e| t.error("oper_expression", i, e))?;
+
+                let assembly = assemblies.entry(id.clone()).or_insert(Assembly {
+                    id,
+                    ..Default::default()
+                });
+                assembly.chain_copies.append(&mut copies);
             }
         }
 
-        Ok(Structure {
+        Ok(vec![Structure {
+            // Files from the PDB have chem_comp_bond for some of their components and
+            // struct_conn for the links between residues, if they have either
+            infer_bonds: missing_bonds(&atoms, &bonds),
             atoms,
             bonds,
             secondary,
-            chain_copies,
-        })
+            assemblies: assemblies.into_values().collect(),
+            frames,
+            diagnostics,
+            ..Default::default()
+        }])
+    }
+}
+
+impl FileLoader for MMCIFLoader {
+    fn parse_file(&mut self, path: &Path) -> Result<Vec<Structure>, ParseError> {
+        self.parse_structures(path).map_err(|e| e.in_file(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Write the text of a file somewhere the loader can read it from
+    fn load(
+        loader: &mut dyn FileLoader,
+        file_name: &str,
+        text: &str,
+    ) -> Result<Vec<Structure>, ParseError> {
+        let path = std::env::temp_dir().join(format!("chemview_test_{file_name}"));
+        std::fs::write(&path, text).unwrap();
+        let structures = loader.parse_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        structures
+    }
+
+    fn parse_pdb(name: &str, text: &str) -> Structure {
+        let file_name = format!("{name}.pdb");
+        load(&mut PDBLoader {}, &file_name, text).unwrap().remove(0)
+    }
+
+    // Fields are found by their columns, even when there's no space between them
+    #[test]
+    fn parses_pdb_columns() {
+        let text = "\
+ATOM      1  CA AALA B  52B   -100.123-200.456   3.000  0.50 12.34           C
+HETATM    2 CA    CA C 301       1.000   2.000   3.000  1.00 20.00            2+
+";
+        let structure = parse_pdb("columns", text);
+        let [protein, ion] = &structure.atoms[..] else {
+            panic!("expected two atoms");
+        };
+
+        assert_eq!(protein.atom_id, "CA");
+        assert_eq!(protein.component_name, "ALA");
+        assert_eq!(protein.chain_id, "B");
+        assert_eq!(protein.sequence_id.number, 52);
+        assert_eq!(protein.sequence_id.insertion, Some('B'));
+        assert_eq!(protein.alt_id, Some('A'));
+        assert_eq!(protein.position, Vec3::new(-100.123, -200.456, 3.0));
+        assert_eq!(protein.occupancy, Some(0.5));
+        assert_eq!(protein.b_factor, Some(12.34));
+        assert_eq!(protein.element, Element::C);
+        assert!(!protein.is_ligand);
+
+        // Without an element column, "CA  " is calcium rather than an alpha carbon
+        assert_eq!(ion.element, Element::from_symbol("Ca"));
+        assert_eq!(ion.charge, 2);
+        assert_eq!(ion.alt_id, None);
+        assert_eq!(ion.sequence_id.insertion, None);
+        assert!(ion.is_ligand);
+    }
+
+    // Only the first model is loaded, along with the CONECT records after the last one
+    #[test]
+    fn loads_first_model() {
+        let text = "\
+MODEL        1
+ATOM      1  C1  LIG A   1       0.000   0.000   0.000  1.00  0.00           C
+ATOM      2  C2  LIG A   1       1.500   0.000   0.000  1.00  0.00           C
+ENDMDL
+MODEL        2
+ATOM      1  C1  LIG A   1       0.000   5.000   0.000  1.00  0.00           C
+ATOM      2  C2  LIG A   1       1.500   5.000   0.000  1.00  0.00           C
+ENDMDL
+CONECT    1    2
+CONECT    2    1
+END
+";
+        let structure = parse_pdb("models", text);
+        assert_eq!(structure.atoms.len(), 2);
+        assert!(structure.atoms.iter().all(|atom| atom.position.y == 0.0));
+
+        // Each bond is listed under both of its atoms, but only loaded once
+        assert_eq!(structure.bonds.len(), 1);
+        assert_eq!((structure.bonds[0].src, structure.bonds[0].dst), (0, 1));
+        assert!(!structure.infer_bonds);
+    }
+
+    #[test]
+    fn parses_biomt_assemblies() {
+        let text = "\
+REMARK 350 BIOMOLECULE: 1
+REMARK 350 AUTHOR DETERMINED BIOLOGICAL UNIT: DIMERIC
+REMARK 350 APPLY THE FOLLOWING TO CHAINS: A, B
+REMARK 350   BIOMT1   1  1.000000  0.000000  0.000000        0.00000
+REMARK 350   BIOMT2   1  0.000000  1.000000  0.000000        0.00000
+REMARK 350   BIOMT3   1  0.000000  0.000000  1.000000        0.00000
+REMARK 350   BIOMT1   2 -1.000000  0.000000  0.000000       10.00000
+REMARK 350   BIOMT2   2  0.000000 -1.000000  0.000000        0.00000
+REMARK 350   BIOMT3   2  0.000000  0.000000  1.000000       -5.00000
+ATOM      1  CA  ALA A   1       1.000   2.000   3.000  1.00  0.00           C
+ATOM      2  CA  ALA B   1       4.000   5.000   6.000  1.00  0.00           C
+";
+        let structure = parse_pdb("biomt", text);
+        let [assembly] = &structure.assemblies[..] else {
+            panic!("expected one assembly");
+        };
+        assert_eq!(assembly.id, "1");
+        assert_eq!(assembly.details, "author_defined_assembly");
+        assert_eq!(assembly.oligomeric, "dimeric");
+
+        let chains: Vec<&str> = assembly
+            .chain_copies
+            .iter()
+            .map(|(c, _)| c.as_str())
+            .collect();
+        assert_eq!(chains, ["A", "B", "A", "B"]);
+        assert_eq!(assembly.chain_copies[0].1, Mat4::IDENTITY);
+
+        // The second operator rotates around z and then translates
+        let transform = assembly.chain_copies[2].1;
+        let point = transform.transform_point3(Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(point, Vec3::new(9.0, -2.0, -2.0));
+    }
+
+    // Ranges run over whole residues, and the ones that are backwards or go from one
+    // chain to another are left out
+    #[test]
+    fn checks_secondary_ranges() {
+        let text = "\
+HELIX    1   1 ALA A    2  ALA A    1 1
+HELIX    2   2 ALA A    1  ALA B    2 1
+SHEET    1  S1 1 ALA A   1  ALA A   2
+ATOM      1  N   ALA A   1       1.000   0.000   0.000  1.00  0.00           N
+ATOM      2  CA  ALA A   1       2.000   0.000   0.000  1.00  0.00           C
+ATOM      3  C   ALA A   1       3.000   0.000   0.000  1.00  0.00           C
+ATOM      4  N   ALA A   2       4.000   0.000   0.000  1.00  0.00           N
+ATOM      5  CA  ALA A   2       5.000   0.000   0.000  1.00  0.00           C
+ATOM      6  C   ALA A   2       6.000   0.000   0.000  1.00  0.00           C
+ATOM      7  N   ALA B   1       7.000   0.000   0.000  1.00  0.00           N
+ATOM      8  CA  ALA B   1       8.000   0.000   0.000  1.00  0.00           C
+ATOM      9  C   ALA B   1       9.000   0.000   0.000  1.00  0.00           C
+ATOM     10  N   ALA B   2      10.000   0.000   0.000  1.00  0.00           N
+ATOM     11  CA  ALA B   2      11.000   0.000   0.000  1.00  0.00           C
+ATOM     12  C   ALA B   2      12.000   0.000   0.000  1.00  0.00           C
+";
+        let structure = parse_pdb("secondary", text);
+        let [sheet] = &structure.secondary[..] else {
+            panic!("expected only the sheet");
+        };
+        assert_eq!(sheet.struct_type, SecondaryType::BetaSheet);
+        assert_eq!((sheet.start, sheet.end), (0, 5));
+
+        let messages: Vec<(&str, usize)> = structure.diagnostics.iter().collect();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].0.contains("Range ends before it starts"));
+        assert!(
+            messages[1]
+                .0
+                .contains("Range starts in chain A and ends in chain B")
+        );
+    }
+
+    fn parse_mmcif(name: &str, text: &str) -> Structure {
+        let file_name = format!("{name}.cif");
+        load(&mut MMCIFLoader::default(), &file_name, text)
+            .unwrap()
+            .remove(0)
+    }
+
+    // Residues are linked in order, over insertion codes and skipped numbers, unless
+    // they're too far apart
+    #[test]
+    fn links_polymer_residues() {
+        let text = "\
+data_TEST
+loop_
+_atom_site.group_PDB
+_atom_site.id
+_atom_site.type_symbol
+_atom_site.label_atom_id
+_atom_site.label_comp_id
+_atom_site.label_asym_id
+_atom_site.label_seq_id
+_atom_site.Cartn_x
+_atom_site.Cartn_y
+_atom_site.Cartn_z
+ATOM 1 N N GLY A 51 0.000 0.000 0.000
+ATOM 2 C C GLY A 51 1.500 0.000 0.000
+ATOM 3 N N GLY A 52 3.000 0.000 0.000
+ATOM 4 C C GLY A 52 4.500 0.000 0.000
+ATOM 5 N N GLY A 52A 6.000 0.000 0.000
+ATOM 6 C C GLY A 52A 7.500 0.000 0.000
+ATOM 7 N N GLY A 60 9.000 0.000 0.000
+ATOM 8 C C GLY A 60 10.500 0.000 0.000
+ATOM 9 N N GLY A 61 100.000 0.000 0.000
+ATOM 10 C C GLY A 61 101.500 0.000 0.000
+";
+        let structure = parse_mmcif("polymer_links", text);
+        let links: Vec<(usize, usize)> = structure.bonds.iter().map(|b| (b.src, b.dst)).collect();
+        assert_eq!(links, [(1, 2), (3, 4), (5, 6)]);
+    }
+
+    // V2000 fields are read by their columns, so numbers that fill them still parse
+    #[test]
+    fn parses_fused_v2000_fields() {
+        let mut text =
+            String::from("chain\n  chemview\n\n101100  0  0  0  0  0  0  0  0999 V2000\n");
+        for i in 0..101 {
+            // Charge code 3 is +1
+            let charge = if i == 0 { 3 } else { 0 };
+            let x = i as f32 * 1.5;
+            text += &format!("{x:>10.4}    0.0000    0.0000 C   0{charge:>3}  0  0  0  0\n");
+        }
+        for i in 1..101 {
+            text += &format!("{i:>3}{:>3}  1  0  0  0  0\n", i + 1);
+        }
+        text += "M  ISO  1 101  13\nM  END\n$$$$\n";
+
+        let structures = load(&mut SDFLoader {}, "fused.sdf", &text).unwrap();
+
+        let structure = &structures[0];
+        assert_eq!(structure.atoms.len(), 101);
+        assert_eq!(structure.bonds.len(), 100);
+        let last = &structure.bonds[99];
+        assert_eq!((last.src, last.dst), (99, 100));
+        assert_eq!(structure.atoms[0].charge, 1);
+        assert_eq!(structure.atoms[100].isotope, 13);
+        assert_eq!(structure.atoms[100].position.x, 150.0);
+    }
+
+    // The tokens of a data block, with values in brackets
+    fn tokens(text: &str) -> Vec<String> {
+        let file = text.as_bytes();
+        let mut lexer = Lexer {
+            file,
+            bytes: file,
+            offset: 0,
+            i: 0,
+            start: 0,
+        };
+        let mut tokens = Vec::new();
+        loop {
+            tokens.push(match lexer.next_token().unwrap() {
+                Token::Eof => break,
+                Token::TableStart => String::from("loop_"),
+                Token::Label(name) => format!("_{name}"),
+                Token::SaveStart => String::from("save start"),
+                Token::SaveEnd => String::from("save end"),
+                Token::Value(span) if span.start == Span::UNKNOWN.start => String::from("?"),
+                Token::Value(span) if span.is_null() => String::from("."),
+                Token::Value(span) => {
+                    let start = span.start as usize;
+                    let value = &text[start..start + span.len as usize];
+                    format!("[{value}]")
+                }
+            });
+        }
+        tokens
+    }
+
+    // A quote only ends a quoted value when whitespace follows it
+    #[test]
+    fn lexes_quotes_inside_quoted_values() {
+        let text = "_a 'O5'' _b \"N\"-methyl\" _c 'it's' _d \"say \"hi\"\"\n";
+        assert_eq!(
+            tokens(text),
+            [
+                "_a",
+                "[O5']",
+                "_b",
+                "[N\"-methyl]",
+                "_c",
+                "[it's]",
+                "_d",
+                "[say \"hi\"]"
+            ]
+        );
+    }
+
+    // Text fields run from a ; at the start of a line to the next one, and a ;
+    // anywhere else is part of a value
+    #[test]
+    fn lexes_text_fields() {
+        let text = "_a\n;first line\nsecond ; line\n;\n_b a;b\n_c\n;\n;\n";
+        assert_eq!(
+            tokens(text),
+            [
+                "_a",
+                "[first line\nsecond ; line]",
+                "_b",
+                "[a;b]",
+                "_c",
+                "[]"
+            ]
+        );
+    }
+
+    #[test]
+    fn lexes_save_frames() {
+        let text = "save_frame\n_a 1\nSAVE_\nloop_ _b 2\n";
+        assert_eq!(
+            tokens(text),
+            ["save start", "_a", "[1]", "save end", "loop_", "_b", "[2]"]
+        );
+    }
+
+    // Only bare ? and . are null. Quoted, they're values like any other.
+    #[test]
+    fn lexes_null_values() {
+        let text = "_a ? _b . _c '?' _d \".\" _e .5 _f ?x\n";
+        assert_eq!(
+            tokens(text),
+            [
+                "_a", "?", "_b", ".", "_c", "[?]", "_d", "[.]", "_e", "[.5]", "_f", "[?x]"
+            ]
+        );
+    }
+
+    // A # starts a comment at the start of a token, but not inside of one
+    #[test]
+    fn lexes_comments_after_values() {
+        let text = "_a 1.0 # comment 'x\n_b 'a # b' # c\n_c x#y\n# _d 2\n";
+        assert_eq!(
+            tokens(text),
+            ["_a", "[1.0]", "_b", "[a # b]", "_c", "[x#y]"]
+        );
+    }
+
+    // Run with `cargo test --release -- --ignored --nocapture parse_speed` to time the
+    // loading of a generated file
+    #[test]
+    #[ignore]
+    fn parse_speed() {
+        let num_atoms = 500_000;
+        let mut text = String::from("data_BENCH\nloop_\n");
+        let columns = [
+            "group_PDB",
+            "id",
+            "type_symbol",
+            "label_atom_id",
+            "label_alt_id",
+            "label_comp_id",
+            "label_asym_id",
+            "label_seq_id",
+            "Cartn_x",
+            "Cartn_y",
+            "Cartn_z",
+            "occupancy",
+            "B_iso_or_equiv",
+            "pdbx_PDB_model_num",
+        ];
+        for column in columns {
+            text += &format!("_atom_site.{column}\n");
+        }
+        for i in 0..num_atoms {
+            let (residue, atom) = (i / 4, ["N", "CA", "C", "O"][i % 4]);
+            let (x, y, z) = (residue as f32 * 3.8, (i % 4) as f32, 0.0);
+            text += &format!(
+                "ATOM {} {} {atom} . ALA A {} {x:.3} {y:.3} {z:.3} 1.00 10.00 1\n",
+                i + 1,
+                &atom[..1],
+                residue + 1
+            );
+        }
+        let path = std::env::temp_dir().join("chemview_test_parse_speed.cif");
+        std::fs::write(&path, text).unwrap();
+
+        let start = std::time::Instant::now();
+        let structures = MMCIFLoader::default().parse_file(&path).unwrap();
+        println!("{num_atoms} atoms in {:?}", start.elapsed());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(structures[0].atoms.len(), num_atoms);
+    }
+
+    // Every file in data/malformed is truncated or corrupted in some way,
+    // and has to be rejected with an error instead of a panic
+    #[test]
+    fn rejects_malformed_files() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("data/malformed");
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+
+        for path in paths {
+            let mut loader: Box<dyn FileLoader> = match path.extension().and_then(|e| e.to_str()) {
+                Some("sdf") => Box::new(SDFLoader {}),
+                Some("cif") => Box::new(MMCIFLoader::default()),
+                _ => continue,
+            };
+            match loader.parse_file(&path) {
+                Ok(_) => panic!("{} was accepted", path.display()),
+                Err(e) => assert!(!e.message.is_empty()),
+            }
+
+            // Make sure these are broken the way they're named after
+            let name = path.file_stem().unwrap().to_string_lossy();
+            if name.ends_with("invalid_utf8") {
+                let bytes = std::fs::read(&path).unwrap();
+                assert!(
+                    std::str::from_utf8(&bytes).is_err(),
+                    "{name} is valid UTF-8"
+                );
+            }
+        }
+    }
+
+    // Errors point at the line and column of the problem, and the table and field
+    // of an mmCIF value
+    #[test]
+    fn reports_error_locations() {
+        type Location<'a> = (Option<u32>, Option<u32>, Option<&'a str>, Option<&'a str>);
+        fn location(e: &ParseError) -> Location<'_> {
+            (e.line, e.column, e.table.as_deref(), e.field.as_deref())
+        }
+
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("data/malformed");
+        let mut loader = MMCIFLoader::default();
+
+        // A row of the loop is cut short, which is reported at the start of the loop
+        let e = loader
+            .parse_file(&dir.join("cif_truncated_row.cif"))
+            .unwrap_err();
+        assert_eq!(location(&e), (Some(5), Some(1), None, None));
+        assert_eq!(
+            e.message,
+            "atom_site has 36 values, which isn't a multiple of its 14 columns"
+        );
+
+        let e = loader
+            .parse_file(&dir.join("cif_invalid_coordinate.cif"))
+            .unwrap_err();
+        assert_eq!(
+            location(&e),
+            (Some(21), Some(23), Some("atom_site"), Some("Cartn_x"))
+        );
+        assert_eq!(e.message, "Invalid number: 1.4x8");
+
+        let text = "\
+ATOM      1  N   ALA A   1       0.000   0.000   0.000  1.00  0.00           N
+ATOM      2  CA  ALA A   1       1.5x0   0.000   0.000  1.00  0.00           C
+";
+        let e = load(&mut PDBLoader {}, "bad_coordinate.pdb", text).unwrap_err();
+        assert_eq!(location(&e), (Some(2), Some(31), None, None));
+        assert_eq!(e.message, "Invalid value in columns 31-38: 1.5x0");
+
+        // The first missing atom would be on line 5
+        let text = "3\nwater\nO 0.0 0.0 0.0\nH 0.96 0.0 0.0\n";
+        let e = load(&mut XYZLoader {}, "truncated.xyz", text).unwrap_err();
+        assert_eq!(location(&e), (Some(5), None, None, None));
+        assert_eq!(e.message, "Frame 1: Expected 3 atoms, found 2");
+    }
+
+    // Ids that don't fit in a label load in full, and stay distinct from each other
+    #[test]
+    fn keeps_long_ids() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("data/long_atom_id.cif");
+        let structures = MMCIFLoader::default().parse_file(&path).unwrap();
+        let structure = &structures[0];
+        assert_eq!(structure.atoms[1].atom_id, "CALCIUMION");
+        assert_eq!(structure.diagnostics.iter().count(), 0);
+
+        let a: Label = "CALCIUMION1".parse().unwrap();
+        let b: Label = "CALCIUMION2".parse().unwrap();
+        assert_ne!(a, b);
+        assert_eq!(a, "CALCIUMION1".parse::<Label>().unwrap());
+        assert!(a < b && "CALCIUM".parse::<Label>().unwrap() < a);
     }
 }
@@ -2,7 +2,13 @@ use crate::app::App;
 use winit::event_loop::{ControlFlow, EventLoop};
 
 mod app;
+mod bonding;
 mod camera;
+mod cartoon;
+mod ccd;
+mod dssp;
+mod element;
+mod hierarchy;
 mod loader;
 mod renderer;
 mod shader;
@@ -8,20 +8,20 @@ use std::sync::Arc;
 use std::time::SystemTime;
 
 use wgpu::{
-    BindGroup, BindGroupLayout, Buffer, BufferAddress, BufferUsages, CommandEncoder,
-    DepthBiasState, DepthStencilState, Device, DeviceDescriptor, Extent3d, FragmentState, LoadOp,
-    MultisampleState, Operations, PipelineLayoutDescriptor, PrimitiveState, Queue,
-    RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor,
-    RenderPipeline, RenderPipelineDescriptor, RequestAdapterOptions, ShaderModuleDescriptor,
-    StencilState, Surface, TextureDescriptor, TextureFormat, TextureUsages, TextureView,
-    TextureViewDescriptor, VertexAttribute, VertexBufferLayout, VertexFormat, VertexState,
-    VertexStepMode,
+    BindGroup, BindGroupLayout, BlendState, Buffer, BufferAddress, BufferUsages, ColorTargetState,
+    ColorWrites, CommandEncoder, DepthBiasState, DepthStencilState, Device, DeviceDescriptor,
+    Extent3d, FragmentState, LoadOp, MultisampleState, Operations, PipelineLayoutDescriptor,
+    PrimitiveState, Queue, RenderPassColorAttachment, RenderPassDepthStencilAttachment,
+    RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, RequestAdapterOptions,
+    ShaderModuleDescriptor, StencilState, Surface, TextureDescriptor, TextureFormat, TextureUsages,
+    TextureView, TextureViewDescriptor, VertexAttribute, VertexBufferLayout, VertexFormat,
+    VertexState, VertexStepMode,
     util::{BufferInitDescriptor, DeviceExt},
 };
 use winit::{dpi::PhysicalSize, window::Window};
 
-use crate::shader;
-use crate::shape::{self, Shape, Vertex};
+use crate::shader::{self, MAX_COPIES};
+use crate::shape::{self, Shape, ShapeGroup, Vertex};
 use crate::ui::{DebugUI, UIState};
 use crate::{
     camera::CameraController,
@@ -38,6 +38,16 @@ struct ShapeInstance {
     // For each instance
     model_matrices: Vec<[[f32; 4]; 4]>,
     colors: Vec<[f32; 4]>,
+
+    // Range of instances to draw, the copy transform they're drawn with
+    // and whether they're see through
+    draws: Vec<(Range<u32>, u32, bool)>,
+
+    // Buffers and part of the index buffer for each instance, when every instance has its
+    // own mesh. Empty when all of them share the whole mesh.
+    index_ranges: Vec<(usize, Range<u32>)>,
+    mesh_data: Vec<(Vec<Vertex>, Vec<u32>)>,
+    mesh_buffers: Vec<(Buffer, Buffer)>,
 }
 
 impl ShapeInstance {
@@ -66,17 +76,67 @@ impl ShapeInstance {
             num_indices: indices.len() as u32,
             model_matrices: Vec::new(),
             colors: Vec::new(),
+            draws: Vec::new(),
+            index_ranges: Vec::new(),
+            mesh_data: Vec::new(),
+            mesh_buffers: Vec::new(),
+        }
+    }
+
+    // Append a mesh that is only drawn for the next instance. The meshes are split over
+    // as many buffers as it takes to keep each one under the size limit.
+    fn add_mesh(&mut self, vertices: &[Vertex], indices: &[u32]) {
+        let size = |num_vertices: usize| (num_vertices * size_of::<Vertex>()) as u64;
+        let is_full = self.mesh_data.last().is_none_or(|(v, _)| {
+            !v.is_empty() && size(v.len() + vertices.len()) > MAX_MESH_BUFFER_SIZE
+        });
+        if is_full {
+            self.mesh_data.push((Vec::new(), Vec::new()));
         }
+
+        let chunk = self.mesh_data.len() - 1;
+        let (mesh_vertices, mesh_indices) = &mut self.mesh_data[chunk];
+        let base = mesh_vertices.len() as u32;
+        let start = mesh_indices.len() as u32;
+        mesh_vertices.extend_from_slice(vertices);
+        mesh_indices.extend(indices.iter().map(|i| i + base));
+        self.index_ranges
+            .push((chunk, start..mesh_indices.len() as u32));
+    }
+
+    // Replace the mesh buffers with the meshes that were added
+    fn upload_meshes(&mut self, device: &Device) {
+        self.mesh_buffers = std::mem::take(&mut self.mesh_data)
+            .into_iter()
+            .map(|(vertices, indices)| {
+                let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
+                    label: Some("Vertex buffer (meshes)"),
+                    contents: bytemuck::cast_slice(&vertices),
+                    usage: BufferUsages::VERTEX,
+                });
+                let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
+                    label: Some("Index buffer (meshes)"),
+                    contents: bytemuck::cast_slice(&indices),
+                    usage: BufferUsages::INDEX,
+                });
+                (vertex_buffer, index_buffer)
+            })
+            .collect();
     }
 
-    fn ranges(&self) -> (Range<u32>, Range<u32>) {
-        (0..self.num_indices, 0..self.model_matrices.len() as u32)
+    // The shader finds the copy transform from the instance index. Each copy gets its
+    // own block of indexes that is as large as the number of instances.
+    fn instance_range(&self, (range, copy, _): &(Range<u32>, u32, bool)) -> Range<u32> {
+        let offset = copy * self.model_matrices.len() as u32;
+        range.start + offset..range.end + offset
     }
 }
 
 // The maximum size in bytes of a storage buffer will be 10 MB
 const DEPTH_TEXTURE_FORMAT: TextureFormat = TextureFormat::Depth24Plus;
 const MSAA_SAMPLE_COUNT: u32 = 4;
+// Half of the default limit on the size of a buffer
+const MAX_MESH_BUFFER_SIZE: u64 = 128 << 20;
 
 fn create_texture(
     device: &Device,
@@ -116,6 +176,7 @@ pub struct Renderer {
     msaa_texture: TextureView,
     depth_texture: TextureView,
     instances: HashMap<usize, ShapeInstance>,
+    copy_transforms: Vec<[[f32; 4]; 4]>,
 
     pub ui: DebugUI,
     pub controller: CameraController,
@@ -183,6 +244,11 @@ impl Renderer {
             1,
             ShapeInstance::new(&device, &instance_bind_group_layout, vertices, indices),
         );
+        // Meshes are only known once the structure is tessellated
+        instances.insert(
+            2,
+            ShapeInstance::new(&device, &instance_bind_group_layout, Vec::new(), Vec::new()),
+        );
 
         let vertex_buffers = [VertexBufferLayout {
             array_stride: size_of::<Vertex>() as BufferAddress,
@@ -198,6 +264,11 @@ impl Renderer {
                     offset: offset_of!(Vertex, normal) as u64,
                     shader_location: 1,
                 },
+                VertexAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: offset_of!(Vertex, color) as u64,
+                    shader_location: 2,
+                },
             ],
         }];
 
@@ -217,7 +288,11 @@ impl Renderer {
             fragment: Some(FragmentState {
                 module: &shader,
                 entry_point: Some("fragment_shader"),
-                targets: &[Some(surface_format.add_srgb_suffix().into())],
+                targets: &[Some(ColorTargetState {
+                    format: surface_format.add_srgb_suffix(),
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
                 compilation_options: Default::default(),
             }),
             primitive: PrimitiveState {
@@ -254,6 +329,7 @@ impl Renderer {
             msaa_texture,
             depth_texture,
             instances,
+            copy_transforms: Vec::new(),
 
             ui,
             controller: CameraController::default(),
@@ -303,10 +379,11 @@ impl Renderer {
         self.configure_surface();
     }
 
-    fn add_shape(&mut self, shape: &Shape) {
+    fn add_shape(&mut self, shape: &Shape, opacity: f32) {
         let id = match *shape {
             Shape::Sphere { .. } => 0,
             Shape::Cylinder { .. } => 1,
+            Shape::Mesh { .. } => 2,
         };
         let batch = self.instances.get_mut(&id).unwrap();
 
@@ -318,7 +395,7 @@ impl Renderer {
             } => {
                 let model = Mat4::from_translation(origin) * Mat4::from_scale(Vec3::splat(radius));
                 batch.model_matrices.push(model.to_cols_array_2d());
-                batch.colors.push([color.x, color.y, color.z, 1.0]);
+                batch.colors.push([color.x, color.y, color.z, opacity]);
             }
             Shape::Cylinder {
                 start,
@@ -334,12 +411,22 @@ impl Renderer {
                     * Mat4::from_quat(rotation)
                     * Mat4::from_scale(Vec3::new(radius, radius, length));
                 batch.model_matrices.push(model.to_cols_array_2d());
-                batch.colors.push([color.x, color.y, color.z, 1.0]);
+                batch.colors.push([color.x, color.y, color.z, opacity]);
+            }
+            Shape::Mesh {
+                ref vertices,
+                ref indices,
+            } => {
+                // The vertices are already in place and colored
+                batch.add_mesh(vertices, indices);
+                batch.model_matrices.push(Mat4::IDENTITY.to_cols_array_2d());
+                batch.colors.push([1.0, 1.0, 1.0, opacity]);
             }
         }
     }
 
-    pub fn set_mesh_data(&mut self, data: &(Vec<Shape>, Vec3, Vec3)) {
+    // Returns the number of copies that didn't fit in the buffer and were left out
+    pub fn set_mesh_data(&mut self, data: &(Vec<ShapeGroup>, Vec3, Vec3)) -> usize {
         let target_pos = Vec3::new(0.0, 0.0, 0.0);
         let (bounding_min, bounding_max) = (data.1, data.2);
         let size = bounding_max - bounding_min;
@@ -348,13 +435,56 @@ impl Renderer {
         for instance in self.instances.values_mut() {
             instance.model_matrices.clear();
             instance.colors.clear();
+            instance.draws.clear();
+            instance.index_ranges.clear();
+            instance.mesh_data.clear();
         }
+        self.copy_transforms.clear();
 
+        // Centering is applied after the copies are moved into place
         let offset = (bounding_min + size / 2.0) - target_pos;
-        for shape in &data.0 {
-            let mut copy = shape.clone();
-            copy.translate(offset);
-            self.add_shape(&copy);
+        let center = Mat4::from_translation(-offset);
+        let mut left_out = 0;
+
+        for group in &data.0 {
+            let starts: HashMap<usize, u32> = self
+                .instances
+                .iter()
+                .map(|(id, instance)| (*id, instance.model_matrices.len() as u32))
+                .collect();
+            for shape in &group.shapes {
+                self.add_shape(shape, group.opacity);
+            }
+
+            for transform in &group.transforms {
+                // Copies past the size of the buffer are left out
+                if self.copy_transforms.len() == MAX_COPIES {
+                    left_out += 1;
+                    continue;
+                }
+                let copy = self.copy_transforms.len() as u32;
+                self.copy_transforms
+                    .push((center * *transform).to_cols_array_2d());
+
+                for (id, instance) in self.instances.iter_mut() {
+                    let range = starts[id]..instance.model_matrices.len() as u32;
+                    if !range.is_empty() {
+                        instance.draws.push((range, copy, group.opacity < 1.0));
+                    }
+                }
+            }
+        }
+
+        self.queue.write_buffer(
+            &self.buffers[4],
+            0,
+            bytemuck::cast_slice(&self.copy_transforms),
+        );
+
+        for instance in self.instances.values_mut() {
+            if !instance.index_ranges.is_empty() {
+                instance.upload_meshes(&self.device);
+            }
         }
 
         for instance in self.instances.values() {
@@ -369,7 +499,15 @@ impl Renderer {
                 0,
                 bytemuck::cast_slice(&instance.colors),
             );
+
+            let num_instances = instance.model_matrices.len() as u32;
+            self.queue.write_buffer(
+                &instance.buffers[2],
+                0,
+                bytemuck::cast_slice(&[num_instances, 0, 0, 0]),
+            );
         }
+        left_out
     }
 
     fn update_shader_vars(&mut self) {
@@ -427,13 +565,42 @@ impl Renderer {
             pass.set_pipeline(&self.render_pipeline);
             pass.set_bind_group(0, &self.bind_group, &[]);
 
-            for instance in self.instances.values() {
-                pass.set_bind_group(1, &instance.bind_group, &[]);
-                pass.set_index_buffer(instance.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-                pass.set_vertex_buffer(0, instance.vertex_buffer.slice(..));
-
-                let (index_range, instance_range) = instance.ranges();
-                pass.draw_indexed(index_range, 0, instance_range);
+            // See through shapes are blended over everything else, so they go last
+            for see_through in [false, true] {
+                // The meshes are empty until there's something to draw with them
+                for instance in self.instances.values().filter(|i| !i.draws.is_empty()) {
+                    pass.set_bind_group(1, &instance.bind_group, &[]);
+                    // Instances with their own meshes bind the buffer that each one is in
+                    if instance.index_ranges.is_empty() {
+                        pass.set_index_buffer(
+                            instance.index_buffer.slice(..),
+                            wgpu::IndexFormat::Uint32,
+                        );
+                        pass.set_vertex_buffer(0, instance.vertex_buffer.slice(..));
+                    }
+
+                    let mut bound_chunk = None;
+                    for draw in instance.draws.iter().filter(|d| d.2 == see_through) {
+                        let instances = instance.instance_range(draw);
+                        if instance.index_ranges.is_empty() {
+                            pass.draw_indexed(0..instance.num_indices, 0, instances);
+                            continue;
+                        }
+                        for (k, i) in draw.0.clone().zip(instances) {
+                            let (chunk, indices) = instance.index_ranges[k as usize].clone();
+                            if bound_chunk != Some(chunk) {
+                                let (vertex_buffer, index_buffer) = &instance.mesh_buffers[chunk];
+                                pass.set_index_buffer(
+                                    index_buffer.slice(..),
+                                    wgpu::IndexFormat::Uint32,
+                                );
+                                pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+                                bound_chunk = Some(chunk);
+                            }
+                            pass.draw_indexed(indices, 0, i..i + 1);
+                        }
+                    }
+                }
             }
         }
     }
@@ -93,7 +93,10 @@ pub fn create_buffers(
     (buffers, group)
 }
 
-pub const GLOBAL_SHADER_VARS: [ShaderVar; 4] = [
+// Maximum number of times the shapes can be copied, like the chains in a symmetric assembly
+pub const MAX_COPIES: usize = 4096;
+
+pub const GLOBAL_SHADER_VARS: [ShaderVar; 5] = [
     ShaderVar {
         is_f32: true,
         is_storage: false,
@@ -118,11 +121,17 @@ pub const GLOBAL_SHADER_VARS: [ShaderVar; 4] = [
         num_bytes: 4,
         label: "Camera position",
     },
+    ShaderVar {
+        is_f32: true,
+        is_storage: true,
+        num_bytes: 16 * MAX_COPIES,
+        label: "Copy transforms",
+    },
 ];
 
 const STORAGE_BUFFER_SIZE: usize = 10 * 1024 * 1024;
 
-pub const INSTANCE_SHADER_VARS: [ShaderVar; 2] = [
+pub const INSTANCE_SHADER_VARS: [ShaderVar; 3] = [
     ShaderVar {
         is_f32: true,
         is_storage: true,
@@ -135,4 +144,10 @@ pub const INSTANCE_SHADER_VARS: [ShaderVar; 2] = [
         num_bytes: STORAGE_BUFFER_SIZE,
         label: "Colors",
     },
+    ShaderVar {
+        is_f32: false,
+        is_storage: false,
+        num_bytes: 4,
+        label: "Number of instances",
+    },
 ];
@@ -3,10 +3,12 @@
 @group(0) @binding(1) var<uniform> view_matrix: mat4x4<f32>;
 @group(0) @binding(2) var<uniform> object_rotation: mat4x4<f32>;
 @group(0) @binding(3) var<uniform> camera_pos: vec4<f32>;
+@group(0) @binding(4) var<storage, read> copy_transforms: array<mat4x4<f32>>;
 
 // Bind group 1: for instance data
 @group(1) @binding(0) var<storage, read> model_matrices: array<mat4x4<f32>>;
 @group(1) @binding(1) var<storage, read> colors: array<vec4<f32>>;
+@group(1) @binding(2) var<uniform> num_instances: vec4<u32>;
 
 struct VertexOutput {
     @builtin(position) pos: vec4<f32>,
@@ -19,11 +21,16 @@ struct VertexOutput {
 fn vertex_shader(
     @location(0) position: vec4<f32>,
     @location(1) normal: vec4<f32>,
+    @location(2) color: vec4<f32>,
     @builtin(instance_index) i: u32) -> VertexOutput {
+    // Each copy of the instances has its own block of instance indexes
+    let instance = i % num_instances.x;
+    let copy = i / num_instances.x;
+
     var v: VertexOutput;
-    v.world_pos = object_rotation * model_matrices[i] * position;
+    v.world_pos = object_rotation * copy_transforms[copy] * model_matrices[instance] * position;
     v.pos = projection_matrix * view_matrix * v.world_pos;
-    v.color = colors[i];
+    v.color = colors[instance] * color;
     v.normal = normal;
     return v;
 }
@@ -45,5 +52,5 @@ fn fragment_shader(v: VertexOutput) -> @location(0) vec4<f32> {
     let spec = pow(max(dot(v.normal.xyz, halfway), 0.0), shininess);
     let specular = 0.15 * spec * light_color;
 
-    return vec4<f32>(ambient + diffuse + specular, 1.0);
+    return vec4<f32>(ambient + diffuse + specular, v.color.a);
 }
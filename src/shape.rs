@@ -1,5 +1,5 @@
 use bytemuck::{Pod, Zeroable};
-use glam::Vec3;
+use glam::{Mat4, Vec3};
 use std::collections::HashMap;
 use std::f32;
 use std::f32::consts::PI;
@@ -19,6 +19,19 @@ pub enum Shape {
         color: Vec3,
         radius: f32,
     },
+    // Triangles that are drawn as they are, like the cartoon of a protein chain
+    Mesh {
+        vertices: Vec<Vertex>,
+        indices: Vec<u32>,
+    },
+}
+
+// Shapes that are drawn once for each transform, like the copies of
+// the chains in a symmetric assembly
+pub struct ShapeGroup {
+    pub shapes: Vec<Shape>,
+    pub transforms: Vec<Mat4>,
+    pub opacity: f32, // less than 1 to draw the shapes see through
 }
 
 impl Shape {
@@ -34,16 +47,13 @@ impl Shape {
                 start.min(end) - Vec3::splat(radius), // leftmost
                 start.max(end) + Vec3::splat(radius), // rightmost
             ),
-        }
-    }
-
-    pub fn translate(&mut self, offset: Vec3) {
-        match self {
-            Shape::Sphere { origin, .. } => *origin -= offset,
-            Shape::Cylinder { start, end, .. } => {
-                *start -= offset;
-                *end -= offset;
-            }
+            Shape::Mesh { ref vertices, .. } => vertices.iter().fold(
+                (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+                |(min, max), v| {
+                    let position = Vec3::from_slice(&v.position[..3]);
+                    (min.min(position), max.max(position))
+                },
+            ),
         }
     }
 }
@@ -77,13 +87,19 @@ impl Hash for Shape {
 pub struct Vertex {
     pub position: [f32; 4],
     pub normal: [f32; 4],
+    pub color: [f32; 4], // multiplied with the color of the shape
 }
 
 impl Vertex {
     fn from(pos: Vec3, normal: Vec3) -> Vertex {
+        Vertex::colored(pos, normal, Vec3::ONE)
+    }
+
+    pub fn colored(pos: Vec3, normal: Vec3, color: Vec3) -> Vertex {
         Vertex {
             position: [pos[0], pos[1], pos[2], 1.0],
             normal: [normal[0], normal[1], normal[2], 0.0],
+            color: [color[0], color[1], color[2], 1.0],
         }
     }
 }
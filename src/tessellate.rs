@@ -1,21 +1,45 @@
-use glam::{Mat4, Vec3};
+use glam::{BVec3, Mat3, Mat4, Vec3};
+use indexmap::IndexMap;
+use itertools::Itertools;
 use serde::Deserialize;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::f32;
 use std::fmt::{self, Display, Formatter};
+use std::ops::Range;
 use std::path::PathBuf;
 
-use crate::shape::Shape;
+use crate::bonding;
+use crate::cartoon::{self, Guide, Profile};
+use crate::element::Element;
+use crate::hierarchy::{Hierarchy, Label, Residue, SeqId};
+use crate::shape::{Shape, ShapeGroup};
 
 #[derive(Default, Debug)]
 pub struct Atom {
-    pub chain_id: String,
-    pub sequence_id: String,
-    pub component_name: String,
-    pub atom_id: String,
-    pub element: String,
+    pub chain_id: Label,
+    pub sequence_id: SeqId,
+    pub component_name: Label,
+    pub atom_id: Label,
+    pub element: Element,
     pub is_ligand: bool,
     pub position: Vec3,
+    pub charge: i8,             // formal charge
+    pub isotope: u16,           // mass number, 0 for the natural isotope mix
+    pub radical: u8,            // 0 = none, 1 = singlet, 2 = doublet, 3 = triplet
+    pub alt_id: Option<char>,   // alternate location, for atoms modeled in more than one place
+    pub occupancy: Option<f32>, // fraction of the crystal where the atom is at this location
+    pub b_factor: Option<f32>,  // isotropic displacement, in square angstroms
+}
+
+impl Atom {
+    // Whether the two atoms can be in the structure at the same time. Alternate locations
+    // of a residue are separate conformers, so they're never bonded to each other.
+    pub fn same_conformer(&self, other: &Atom) -> bool {
+        match (self.alt_id, other.alt_id) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        }
+    }
 }
 
 #[derive(Default, Debug, Copy, Clone)]
@@ -24,14 +48,39 @@ pub enum BondType {
     Single,
     Double,
     Triple,
+    Quadruple,
+    Aromatic,
+    Delocalized,
+    Dative, // from the electron donor (src) to the acceptor (dst)
     HBond,
+    Disulfide,
+    MetalCoordination,
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
 pub enum SecondaryType {
     #[default]
     AlphaHelix,
-    BetaSheet,
+    Helix310,
+    PiHelix,
+    PolyprolineHelix,
+    OtherHelix, // gamma, omega and 2-7 helices, left-handed helices and nucleic acid helices
+    BetaSheet,  // a strand of a sheet
+    Turn,
+    Bend,
+}
+
+impl SecondaryType {
+    pub fn is_helix(&self) -> bool {
+        matches!(
+            self,
+            SecondaryType::AlphaHelix
+                | SecondaryType::Helix310
+                | SecondaryType::PiHelix
+                | SecondaryType::PolyprolineHelix
+                | SecondaryType::OtherHelix
+        )
+    }
 }
 
 #[derive(Default, Debug)]
@@ -44,19 +93,142 @@ pub struct Bond {
 #[derive(Default, Debug)]
 pub struct SecondaryStructure {
     pub struct_type: SecondaryType,
-    pub start: usize,
-    pub end: usize,
+    pub start: usize,             // first atom of the first residue
+    pub end: usize,               // last atom of the last residue
+    pub sheet_id: Option<String>, // the sheet a strand belongs to
+}
+
+// A biological assembly: copies of chains, each moved into place by a transform
+#[derive(Default, Debug)]
+pub struct Assembly {
+    pub id: String,
+    pub details: String, // how it was determined, like "author_defined_assembly"
+    pub oligomeric: String, // like "dimeric"
+    pub chain_copies: Vec<(String, Mat4)>,
+}
+
+impl Display for Assembly {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.id)?;
+        if !self.oligomeric.is_empty() {
+            write!(f, " ({})", self.oligomeric)?;
+        }
+        if !self.details.is_empty() {
+            write!(f, ", {}", self.details.replace('_', " "))?;
+        }
+        Ok(())
+    }
+}
+
+// A set of atoms that are shown together, like one step of a trajectory
+#[derive(Default, Debug)]
+pub struct Frame {
+    pub atoms: Range<usize>,
+    pub cell: Option<Mat3>, // unit cell vectors as columns
+    pub properties: IndexMap<String, String>,
+}
+
+// Problems with a file that didn't stop it from loading, like atoms with an unknown
+// element. Repeated messages are only kept once, along with how many times they came up.
+#[derive(Default, Debug)]
+pub struct Diagnostics(IndexMap<String, usize>);
+
+impl Diagnostics {
+    pub fn warn(&mut self, message: impl Into<String>) {
+        *self.0.entry(message.into()).or_default() += 1;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.0
+            .iter()
+            .map(|(message, count)| (message.as_str(), *count))
+    }
 }
 
 #[derive(Default, Debug)]
 pub struct Structure {
+    pub name: String,
+    pub properties: IndexMap<String, String>, // data items attached to the record
     pub atoms: Vec<Atom>,
     pub bonds: Vec<Bond>,
     pub secondary: Vec<SecondaryStructure>,
-    pub chain_copies: Vec<(String, Mat4)>,
+    pub assemblies: Vec<Assembly>,
+    pub frames: Vec<Frame>, // empty when all the atoms are in a single frame
+    pub infer_bonds: bool,  // the file is missing some connectivity, so bonds come from distances
+    pub diagnostics: Diagnostics,
+}
+
+impl Structure {
+    pub fn num_frames(&self) -> usize {
+        self.frames.len().max(1)
+    }
+
+    pub fn frame_atoms(&self, index: usize) -> Range<usize> {
+        match self.frames.get(index.min(self.num_frames() - 1)) {
+            Some(frame) => frame.atoms.clone(),
+            None => 0..self.atoms.len(),
+        }
+    }
+
+    // Models (the frames), chains and residues of the atoms
+    pub fn hierarchy(&self) -> Hierarchy<'_> {
+        let models = (0..self.num_frames()).map(|frame| self.frame_atoms(frame));
+        Hierarchy::new(&self.atoms, models)
+    }
+
+    // Insert atoms that are bonded to an existing atom right after it,
+    // so that residues and frames stay contiguous
+    pub fn insert_attached_atoms(&mut self, mut attached: Vec<(usize, Atom, BondType)>) {
+        attached.sort_by_key(|(parent, ..)| *parent);
+        let mut attached = attached.into_iter().peekable();
+
+        let old_atoms = std::mem::take(&mut self.atoms);
+        let mut new_index = Vec::with_capacity(old_atoms.len());
+        let mut new_bonds = Vec::new();
+
+        for (i, atom) in old_atoms.into_iter().enumerate() {
+            new_index.push(self.atoms.len());
+            self.atoms.push(atom);
+
+            while let Some((_, atom, bond_type)) = attached.next_if(|(parent, ..)| *parent == i) {
+                new_bonds.push(Bond {
+                    src: new_index[i],
+                    dst: self.atoms.len(),
+                    bond_type,
+                });
+                self.atoms.push(atom);
+            }
+        }
+
+        for bond in &mut self.bonds {
+            bond.src = new_index[bond.src];
+            bond.dst = new_index[bond.dst];
+        }
+        self.bonds.append(&mut new_bonds);
+
+        for range in &mut self.secondary {
+            range.start = new_index[range.start];
+            range.end = new_index[range.end];
+        }
+
+        // Frames cover all the atoms in order, so each one runs up to the start of the next
+        let starts: Vec<usize> = self
+            .frames
+            .iter()
+            .map(|f| {
+                new_index
+                    .get(f.atoms.start)
+                    .copied()
+                    .unwrap_or(self.atoms.len())
+            })
+            .collect();
+        for (k, frame) in self.frames.iter_mut().enumerate() {
+            frame.atoms = starts[k]..starts.get(k + 1).copied().unwrap_or(self.atoms.len());
+        }
+    }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 struct ElementInfo {
     waal_radius: f32,
     covalent_radius: f32,
@@ -68,6 +240,7 @@ pub enum RenderStyle {
     Wireframe,
     BallAndStick,
     SpaceFilling,
+    Cartoon,
 }
 
 impl Display for RenderStyle {
@@ -76,60 +249,283 @@ impl Display for RenderStyle {
             RenderStyle::Wireframe => write!(f, "Wireframe"),
             RenderStyle::BallAndStick => write!(f, "Ball and Stick"),
             RenderStyle::SpaceFilling => write!(f, "Space filling"),
+            RenderStyle::Cartoon => write!(f, "Cartoon"),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum ColorScheme {
+    Element,
+    Charge,
+    BFactor,
+    Occupancy,
+}
+
+impl Display for ColorScheme {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            ColorScheme::Element => write!(f, "Element"),
+            ColorScheme::Charge => write!(f, "Charge"),
+            ColorScheme::BFactor => write!(f, "B-factor"),
+            ColorScheme::Occupancy => write!(f, "Occupancy"),
         }
     }
 }
 
+// Which of the alternate locations of a residue to show
+#[derive(PartialEq, Clone, Copy)]
+pub enum AltLocPolicy {
+    HighestOccupancy,
+    Id(char), // residues without it fall back to the highest occupancy
+    All,      // the alternate locations are see through
+}
+
+impl Display for AltLocPolicy {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            AltLocPolicy::HighestOccupancy => write!(f, "Highest occupancy"),
+            AltLocPolicy::Id(id) => write!(f, "{id}"),
+            AltLocPolicy::All => write!(f, "All"),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub struct ViewOptions {
+    pub style: RenderStyle,
+    pub color_scheme: ColorScheme,
+    pub show_interactions: bool, // hydrogen bonds
+    pub frame: usize,
+    pub all_frames: bool, // superpose every frame, like the models of an NMR ensemble
+    pub assembly: Option<usize>, // None for the asymmetric unit
+    pub altloc: AltLocPolicy,
+}
+
+// Opacity of the alternate locations when they're all shown
+const ALTLOC_OPACITY: f32 = 0.4;
+
+// For atoms whose element isn't known
+const UNKNOWN_ELEMENT: ElementInfo = ElementInfo {
+    waal_radius: 1.5,
+    covalent_radius: 0.75,
+    color: [1.0, 0.4, 0.8],
+};
+
 pub struct Tessellator {
-    element_db: HashMap<String, ElementInfo>,
+    element_db: Vec<ElementInfo>, // indexed by atomic number
+    b_factor_range: (f32, f32),   // of the atoms being drawn, to spread the colors over
 }
 
 impl Tessellator {
     pub fn new() -> Result<Tessellator, String> {
-        /*
-        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-            .join("data/mmcif/chemical-component-dictionary.cif");
-        let mut ccd = MMCIFLoader::default();
-        ccd.open_file(&path)?;
-        */
-
         let base = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         let info_path = base.join("data/element_data.json");
         let contents = std::fs::read_to_string(info_path).map_err(|err| err.to_string())?;
-        let element_db = serde_json::from_str(&contents).map_err(|err| err.to_string())?;
+        let elements: HashMap<String, ElementInfo> =
+            serde_json::from_str(&contents).map_err(|err| err.to_string())?;
+
+        let mut element_db = vec![UNKNOWN_ELEMENT; 119];
+        for (symbol, info) in elements {
+            element_db[Element::from_symbol(&symbol).atomic_number()] = info;
+        }
+
+        Ok(Tessellator {
+            element_db,
+            b_factor_range: (0.0, 0.0),
+        })
+    }
+
+    // Fill in the bonds of files that have no (or only partial) connectivity
+    pub fn infer_bonds(&self, structure: &mut Structure) {
+        bonding::infer_bonds(structure, |element| {
+            element
+                .is_known()
+                .then(|| self.element_db[element.atomic_number()].covalent_radius)
+        });
+    }
+
+    fn element_info(&self, element: Element) -> &ElementInfo {
+        &self.element_db[element.atomic_number()]
+    }
+
+    // Draw the 12 edges of the unit cell
+    fn add_cell(shapes: &mut Vec<Shape>, cell: Mat3) {
+        let color = Vec3::new(0.8, 0.8, 0.8);
+        let corner = |i: usize| {
+            let mut position = Vec3::ZERO;
+            for axis in 0..3 {
+                if i & (1 << axis) != 0 {
+                    position += cell.col(axis);
+                }
+            }
+            position
+        };
 
-        Ok(Tessellator { element_db })
+        for i in 0..8 {
+            for axis in 0..3 {
+                // Every edge goes from a corner without the axis to the corner with it
+                if i & (1 << axis) == 0 {
+                    shapes.push(Shape::Cylinder {
+                        start: corner(i),
+                        end: corner(i | (1 << axis)),
+                        color,
+                        radius: 0.02,
+                    });
+                }
+            }
+        }
+    }
+
+    fn atom_color(&self, atom: &Atom, scheme: &ColorScheme) -> Vec3 {
+        match scheme {
+            ColorScheme::Element => Vec3::from_slice(&self.element_info(atom.element).color),
+            // Cations are blue and anions are red, getting more saturated as the charge grows.
+            // Neutral radicals are green, so that they stand out from the rest of the molecule.
+            ColorScheme::Charge => {
+                let strength = (atom.charge.unsigned_abs() as f32 / 2.0).min(1.0);
+                match atom.charge {
+                    0 if atom.radical != 0 => Vec3::new(0.2, 0.9, 0.3),
+                    0 => Vec3::new(0.85, 0.85, 0.85),
+                    c if c > 0 => Vec3::new(0.5, 0.6, 1.0).lerp(Vec3::new(0.1, 0.2, 1.0), strength),
+                    _ => Vec3::new(1.0, 0.5, 0.5).lerp(Vec3::new(1.0, 0.1, 0.1), strength),
+                }
+            }
+            // Well ordered atoms are blue and mobile ones are red
+            ColorScheme::BFactor => match atom.b_factor {
+                Some(b) => {
+                    let (min, max) = self.b_factor_range;
+                    Self::heat_color((b - min) / (max - min).max(f32::EPSILON))
+                }
+                None => Vec3::new(0.5, 0.5, 0.5),
+            },
+            // Atoms that are only there part of the time are red
+            ColorScheme::Occupancy => match atom.occupancy {
+                Some(occupancy) => Self::heat_color(1.0 - occupancy),
+                None => Vec3::new(0.5, 0.5, 0.5),
+            },
+        }
+    }
+
+    // Blue to white to red, as t goes from 0 to 1
+    fn heat_color(t: f32) -> Vec3 {
+        let (cold, neutral, hot) = (
+            Vec3::new(0.2, 0.3, 1.0),
+            Vec3::new(0.9, 0.9, 0.9),
+            Vec3::new(1.0, 0.2, 0.2),
+        );
+        let t = t.clamp(0.0, 1.0);
+        if t < 0.5 {
+            cold.lerp(neutral, t * 2.0)
+        } else {
+            neutral.lerp(hot, t * 2.0 - 1.0)
+        }
+    }
+
+    // Draw a line as a series of short cylinders, colored by the half of the bond they're in
+    fn add_dashes(
+        shapes: &mut Vec<Shape>,
+        (start_pos, end_pos): (Vec3, Vec3),
+        (start_color, end_color): (Vec3, Vec3),
+        radius: f32,
+        dash_length: f32,
+    ) {
+        let length = start_pos.distance(end_pos);
+        let num_dashes = ((length / (dash_length * 2.0)).round() as usize).max(1);
+        let step = 1.0 / num_dashes as f32;
+
+        for i in 0..num_dashes {
+            // Each dash takes up the first half of its step, the rest is the gap
+            let t = (i as f32 + 0.25) * step;
+            shapes.push(Shape::Cylinder {
+                start: start_pos.lerp(end_pos, t),
+                end: start_pos.lerp(end_pos, t + step / 2.0),
+                color: if t + step / 4.0 < 0.5 {
+                    start_color
+                } else {
+                    end_color
+                },
+                radius,
+            });
+        }
     }
 
     fn add_bond(
         shapes: &mut Vec<Shape>,
-        start_pos: Vec3,
-        end_pos: Vec3,
-        start_color: Vec3,
-        end_color: Vec3,
+        (start_pos, end_pos): (Vec3, Vec3),
+        (start_color, end_color): (Vec3, Vec3),
         camera_front: Vec3,
         bond_type: &BondType,
         cap_cylinders: bool,
     ) {
+        enum Line {
+            Solid,
+            Dashed,
+            Dotted,     // thinner, for non-covalent interactions
+            ThinDashed, // for metal coordination
+            Arrow,      // points from the donor (start) to the acceptor (end)
+        }
+
         let bond_radius = 0.04;
         let bond_direction = (end_pos - start_pos).normalize();
         let midpoint = (start_pos + end_pos) / 2.0;
         let view_right = bond_direction.cross(camera_front).normalize();
 
-        let multiplicity = match bond_type {
-            BondType::Single => 1,
-            BondType::Double => 2,
-            BondType::Triple => 3,
-            BondType::HBond => 0, // FIXME!
+        let lines: &[Line] = match bond_type {
+            BondType::Single => &[Line::Solid],
+            BondType::Double => &[Line::Solid, Line::Solid],
+            BondType::Triple => &[Line::Solid, Line::Solid, Line::Solid],
+            BondType::Quadruple => &[Line::Solid, Line::Solid, Line::Solid, Line::Solid],
+            BondType::Aromatic => &[Line::Solid, Line::Dashed],
+            BondType::Delocalized => &[Line::Dashed, Line::Dashed],
+            BondType::Dative => &[Line::Arrow],
+            BondType::HBond => &[Line::Dotted],
+            BondType::Disulfide => &[Line::Solid],
+            BondType::MetalCoordination => &[Line::ThinDashed],
         };
         let spacing = 0.15;
-        let spread = (multiplicity - 1) as f32 * spacing;
+        let spread = (lines.len() as f32 - 1.0).max(0.0) * spacing;
 
         // Orient the bond to be facing the camera
         // Split the bonds in half to handle the wireframe render type cleanly
-        for i in 0..multiplicity {
+        for (i, line) in lines.iter().enumerate() {
             let offset = view_right * (i as f32 * spacing - spread / 2.0);
 
+            let dashes = match line {
+                Line::Dashed => Some((bond_radius, 0.1)),
+                Line::Dotted => Some((bond_radius / 2.0, 0.06)),
+                Line::ThinDashed => Some((bond_radius / 2.0, 0.1)),
+                _ => None,
+            };
+            if let Some((radius, dash_length)) = dashes {
+                Self::add_dashes(
+                    shapes,
+                    (start_pos + offset, end_pos + offset),
+                    (start_color, end_color),
+                    radius,
+                    dash_length,
+                );
+                continue;
+            }
+
+            // The arrow head is a stack of cylinders that narrow towards the acceptor
+            let head_start = if let Line::Arrow = line {
+                let head_start = start_pos.lerp(end_pos, 0.75);
+                let steps = 4;
+                for step in 0..steps {
+                    let t = step as f32 / steps as f32;
+                    shapes.push(Shape::Cylinder {
+                        start: head_start.lerp(end_pos, t) + offset,
+                        end: head_start.lerp(end_pos, t + 1.0 / steps as f32) + offset,
+                        color: end_color,
+                        radius: bond_radius * 3.0 * (1.0 - t),
+                    });
+                }
+                head_start
+            } else {
+                end_pos
+            };
+
             shapes.push(Shape::Cylinder {
                 start: start_pos + offset,
                 end: midpoint + offset,
@@ -139,7 +535,7 @@ impl Tessellator {
 
             shapes.push(Shape::Cylinder {
                 start: midpoint + offset,
-                end: end_pos + offset,
+                end: head_start + offset,
                 color: end_color,
                 radius: bond_radius,
             });
@@ -161,46 +557,66 @@ impl Tessellator {
         }
     }
 
+    // Draw the `owned` atoms and their bonds to the other `visible` atoms. A bond belongs
+    // to the atom with an alternate location if it has one, so that it's see through
+    // along with it, and to the first atom otherwise.
     fn wireframe(
         &mut self,
         structure: &Structure,
+        (owned, visible): (&[bool], &[bool]),
         camera_front: Vec3,
-        wireframe: bool,
+        view: &ViewOptions,
     ) -> (Vec<Shape>, Vec3, Vec3) {
+        let wireframe = view.style == RenderStyle::Wireframe;
+        let scheme = &view.color_scheme;
         let mut sphere_set: HashSet<Shape> = HashSet::new();
         let mut cylinders: Vec<Shape> = Vec::new();
         let mut bounding_min = Vec3::new(f32::MAX, f32::MAX, f32::MAX);
         let mut bounding_max = Vec3::new(f32::MIN, f32::MIN, f32::MIN);
 
         let bond_color = Vec3::new(0.67, 0.67, 0.67);
+        let hbond_color = Vec3::new(0.35, 0.8, 1.0);
         let radius_scale = 0.5;
 
-        for bond in &structure.bonds {
+        let bonds: Vec<&Bond> = structure
+            .bonds
+            .iter()
+            .filter(|b| visible[b.src] && visible[b.dst])
+            .filter(|b| view.show_interactions || !matches!(b.bond_type, BondType::HBond))
+            .collect();
+        let owner = |b: &Bond| match structure.atoms[b.dst].alt_id {
+            Some(_) => b.dst,
+            None => b.src,
+        };
+
+        for bond in bonds.iter().filter(|b| owned[owner(b)]) {
             let src_atom = &structure.atoms[bond.src];
             let dst_atom = &structure.atoms[bond.dst];
-            let src_color = Vec3::from_slice(&self.element_db[&src_atom.element].color);
-            let dst_color = Vec3::from_slice(&self.element_db[&dst_atom.element].color);
+            let src_color = self.atom_color(src_atom, scheme);
+            let dst_color = self.atom_color(dst_atom, scheme);
 
             let src_sphere = Shape::Sphere {
                 origin: src_atom.position,
                 color: src_color,
-                radius: self.element_db[&src_atom.element].covalent_radius * radius_scale,
+                radius: self.element_info(src_atom.element).covalent_radius * radius_scale,
             };
 
             let dst_sphere = Shape::Sphere {
                 origin: dst_atom.position,
                 color: dst_color,
-                radius: self.element_db[&dst_atom.element].covalent_radius * radius_scale,
+                radius: self.element_info(dst_atom.element).covalent_radius * radius_scale,
             };
 
             // Position the bonds spread out horizontally relative to the screen
             // The bonds are centered in between the two atoms
             Self::add_bond(
                 &mut cylinders,
-                src_atom.position,
-                dst_atom.position,
-                if wireframe { src_color } else { bond_color },
-                if wireframe { dst_color } else { bond_color },
+                (src_atom.position, dst_atom.position),
+                match bond.bond_type {
+                    BondType::HBond => (hbond_color, hbond_color),
+                    _ if wireframe => (src_color, dst_color),
+                    _ => (bond_color, bond_color),
+                },
                 camera_front,
                 &bond.bond_type,
                 wireframe,
@@ -214,46 +630,389 @@ impl Tessellator {
                 .max(dst_sphere.bounds().1);
 
             if !wireframe {
-                sphere_set.insert(src_sphere);
-                sphere_set.insert(dst_sphere);
+                if owned[bond.src] {
+                    sphere_set.insert(src_sphere);
+                }
+                if owned[bond.dst] {
+                    sphere_set.insert(dst_sphere);
+                }
             }
         }
 
+        // Atoms without bonds (ions in a salt, waters, ...) would be invisible otherwise
+        let mut is_bonded = vec![false; structure.atoms.len()];
+        for bond in &bonds {
+            is_bonded[bond.src] = true;
+            is_bonded[bond.dst] = true;
+        }
+
+        for i in (0..structure.atoms.len()).filter(|i| owned[*i] && !is_bonded[*i]) {
+            let atom = &structure.atoms[i];
+            let scale = if wireframe {
+                radius_scale / 2.0
+            } else {
+                radius_scale
+            };
+            let sphere = Shape::Sphere {
+                origin: atom.position,
+                color: self.atom_color(atom, scheme),
+                radius: self.element_info(atom.element).covalent_radius * scale,
+            };
+            bounding_min = bounding_min.min(sphere.bounds().0);
+            bounding_max = bounding_max.max(sphere.bounds().1);
+            sphere_set.insert(sphere);
+        }
+
         let mut shapes: Vec<Shape> = sphere_set.iter().cloned().collect();
         shapes.append(&mut cylinders);
         (shapes, bounding_min, bounding_max)
     }
 
-    fn space_filling(&mut self, structure: &Structure) -> (Vec<Shape>, Vec3, Vec3) {
+    fn space_filling(
+        &mut self,
+        structure: &Structure,
+        visible: &[bool],
+        scheme: &ColorScheme,
+    ) -> (Vec<Shape>, Vec3, Vec3) {
         let mut bounding_min = Vec3::new(f32::MAX, f32::MAX, f32::MAX);
         let mut bounding_max = Vec3::new(f32::MIN, f32::MIN, f32::MIN);
         let mut shapes: Vec<Shape> = Vec::new();
 
-        for atom in &structure.atoms {
+        let atoms = structure.atoms.iter().zip(visible).filter(|(_, v)| **v);
+        for (atom, _) in atoms {
             let shape = Shape::Sphere {
                 origin: atom.position,
-                color: Vec3::from_slice(&self.element_db[&atom.element].color),
-                radius: self.element_db[&atom.element].waal_radius,
+                color: self.atom_color(atom, scheme),
+                radius: self.element_info(atom.element).waal_radius,
             };
             bounding_min = bounding_min.min(shape.bounds().0);
             bounding_max = bounding_max.max(shape.bounds().1);
             shapes.push(shape);
         }
 
-        (shapes, bounding_max, bounding_max)
+        (shapes, bounding_min, bounding_max)
     }
 
-    pub fn tessellate(
+    // Color of a residue in the cartoon. Each kind of helix has its own color, and the
+    // strands of each sheet have their own shade of yellow.
+    fn secondary_color(kind: Option<SecondaryType>, sheet: Option<usize>) -> Vec3 {
+        let sheet_shades = [
+            Vec3::new(0.95, 0.8, 0.2),
+            Vec3::new(0.95, 0.62, 0.15),
+            Vec3::new(0.85, 0.85, 0.35),
+            Vec3::new(0.98, 0.9, 0.5),
+        ];
+        match kind {
+            Some(SecondaryType::AlphaHelix) => Vec3::new(0.9, 0.25, 0.35),
+            Some(SecondaryType::Helix310) => Vec3::new(0.95, 0.45, 0.65),
+            Some(SecondaryType::PiHelix) => Vec3::new(0.65, 0.3, 0.85),
+            Some(SecondaryType::PolyprolineHelix | SecondaryType::OtherHelix) => {
+                Vec3::new(0.95, 0.55, 0.3)
+            }
+            Some(SecondaryType::BetaSheet) => sheet_shades[sheet.unwrap_or(0) % sheet_shades.len()],
+            Some(SecondaryType::Turn) => Vec3::new(0.55, 0.75, 0.9),
+            Some(SecondaryType::Bend) | None => Vec3::new(0.85, 0.85, 0.85),
+        }
+    }
+
+    // Draw the protein chains of the `owned` atoms as a cartoon, and everything
+    // else (ligands, waters, nucleic acids, ...) as balls and sticks
+    fn cartoon(
         &mut self,
         structure: &Structure,
+        (owned, visible): (&[bool], &[bool]),
         camera_front: Vec3,
-        view: &RenderStyle,
+        view: &ViewOptions,
     ) -> (Vec<Shape>, Vec3, Vec3) {
-        match view {
-            RenderStyle::BallAndStick | RenderStyle::Wireframe => {
-                self.wireframe(structure, camera_front, view == &RenderStyle::Wireframe)
+        let mut elements: Vec<Option<&SecondaryStructure>> = vec![None; structure.atoms.len()];
+        for s in &structure.secondary {
+            elements[s.start..=s.end].fill(Some(s));
+        }
+        let sheets: Vec<&str> = structure
+            .secondary
+            .iter()
+            .filter_map(|s| s.sheet_id.as_deref())
+            .unique()
+            .collect();
+
+        let find = |residue: &Residue, atom_id: &str| {
+            residue
+                .atoms
+                .clone()
+                .find(|i| owned[*i] && structure.atoms[*i].atom_id == atom_id)
+        };
+
+        // Calcium ions are named CA too, so only the alpha carbons of polymers count
+        let alpha_carbon = |residue: &Residue| {
+            let ca = find(residue, "CA")?;
+            let atom = &structure.atoms[ca];
+            (!atom.is_ligand && atom.element == Element::C).then_some(ca)
+        };
+
+        // Stretches of residues with a CA atom and no gaps in between
+        let hierarchy = structure.hierarchy();
+        let mut segments: Vec<Vec<(Guide, Range<usize>)>> = Vec::new();
+        for chain in hierarchy.models.iter().flat_map(|m| hierarchy.chains(m)) {
+            let mut segment: Vec<(Guide, Range<usize>)> = Vec::new();
+            // Only the first alternate residue at each position is part of the chain
+            let residues = hierarchy.residues(chain);
+            for position in residues.chunk_by(|a, b| a.seq_id == b.seq_id) {
+                let Some((residue, ca)) = position.iter().find_map(|r| Some((r, alpha_carbon(r)?)))
+                else {
+                    continue;
+                };
+                let ca_atom = &structure.atoms[ca];
+                let side = position
+                    .iter()
+                    .filter_map(|r| find(r, "O"))
+                    .find(|o| structure.atoms[*o].same_conformer(ca_atom))
+                    .and_then(|o| {
+                        let c = find(residue, "C")?;
+                        Some(structure.atoms[o].position - structure.atoms[c].position)
+                    })
+                    .unwrap_or(Vec3::ZERO);
+
+                let element = elements[residue.atoms.start];
+                let kind = element.map(|s| s.struct_type);
+                let profile = match kind {
+                    Some(SecondaryType::Helix310) => Profile::NarrowHelix,
+                    // A polyproline helix is stretched out, so it's closer to a coil
+                    Some(SecondaryType::PolyprolineHelix) => Profile::Coil,
+                    Some(kind) if kind.is_helix() => Profile::Helix,
+                    Some(SecondaryType::BetaSheet) => Profile::Strand,
+                    _ => Profile::Coil,
+                };
+                let color = match view.color_scheme {
+                    ColorScheme::Element => {
+                        let sheet = element.and_then(|s| s.sheet_id.as_deref());
+                        Self::secondary_color(
+                            kind,
+                            sheet.and_then(|id| sheets.iter().position(|s| *s == id)),
+                        )
+                    }
+                    _ => self.atom_color(ca_atom, &view.color_scheme),
+                };
+
+                let is_gap = segment.last().is_some_and(|(prev, _)| {
+                    prev.position.distance(ca_atom.position) > cartoon::MAX_CA_DISTANCE
+                });
+                if is_gap {
+                    segments.push(std::mem::take(&mut segment));
+                }
+                let guide = Guide {
+                    position: ca_atom.position,
+                    side,
+                    profile,
+                    color,
+                };
+                let atoms = position[0].atoms.start..position[position.len() - 1].atoms.end;
+                segment.push((guide, atoms));
             }
-            RenderStyle::SpaceFilling => self.space_filling(structure),
+            segments.push(segment);
         }
+
+        let num_residues = segments.iter().map(|s| s.len()).sum();
+        let subdivisions = cartoon::subdivisions(num_residues);
+        let mut shapes: Vec<Shape> = Vec::new();
+        let mut in_cartoon = vec![false; structure.atoms.len()];
+        for segment in segments {
+            let (guides, atoms): (Vec<Guide>, Vec<Range<usize>>) = segment.into_iter().unzip();
+            let Some(mesh) = cartoon::sweep(&guides, subdivisions) else {
+                continue;
+            };
+            shapes.push(mesh);
+            for range in atoms {
+                in_cartoon[range].fill(true);
+            }
+        }
+
+        let rest = |mask: &[bool]| -> Vec<bool> {
+            mask.iter()
+                .zip(&in_cartoon)
+                .map(|(m, c)| *m && !c)
+                .collect()
+        };
+        let (mut others, mut bounding_min, mut bounding_max) = self.wireframe(
+            structure,
+            (&rest(owned), &rest(visible)),
+            camera_front,
+            view,
+        );
+        for shape in &shapes {
+            bounding_min = bounding_min.min(shape.bounds().0);
+            bounding_max = bounding_max.max(shape.bounds().1);
+        }
+        shapes.append(&mut others);
+        (shapes, bounding_min, bounding_max)
+    }
+
+    // Split the visible atoms into groups of chains that share the same copies. Without
+    // an assembly, the atoms are drawn once as they are in the file.
+    fn chain_groups(
+        structure: &Structure,
+        view: &ViewOptions,
+        visible: &[bool],
+    ) -> Vec<(Vec<bool>, Vec<Mat4>)> {
+        let Some(assembly) = view.assembly.and_then(|i| structure.assemblies.get(i)) else {
+            return vec![(visible.to_vec(), vec![Mat4::IDENTITY])];
+        };
+
+        let mut copies: IndexMap<&str, Vec<Mat4>> = IndexMap::new();
+        for (chain, transform) in &assembly.chain_copies {
+            copies.entry(chain.as_str()).or_default().push(*transform);
+        }
+
+        // Chains that are moved by the same operations are usually drawn together,
+        // so a symmetric assembly ends up as a handful of groups
+        let mut groups: Vec<(HashSet<&str>, Vec<Mat4>)> = Vec::new();
+        for (chain, transforms) in copies {
+            match groups.iter_mut().find(|(_, t)| *t == transforms) {
+                Some((chains, _)) => {
+                    chains.insert(chain);
+                }
+                None => groups.push((HashSet::from([chain]), transforms)),
+            }
+        }
+
+        groups
+            .into_iter()
+            .map(|(chains, transforms)| {
+                let atoms = structure.atoms.iter().zip(visible);
+                let mask = atoms
+                    .map(|(atom, v)| *v && chains.contains(atom.chain_id.as_str()))
+                    .collect();
+                (mask, transforms)
+            })
+            .collect()
+    }
+
+    // Hide the alternate locations that aren't picked by the policy. Residues are picked
+    // as a whole, so that the atoms of one conformer aren't mixed with those of another.
+    fn select_altlocs(structure: &Structure, policy: AltLocPolicy, visible: &mut [bool]) {
+        if policy == AltLocPolicy::All {
+            return;
+        }
+
+        let hierarchy = structure.hierarchy();
+        let chains = hierarchy.models.iter().flat_map(|m| hierarchy.chains(m));
+        for chain in chains {
+            // Alternate residues (microheterogeneity) share the sequence id
+            let residues = hierarchy.residues(chain);
+            for position in residues.chunk_by(|a, b| a.seq_id == b.seq_id) {
+                let atoms = position.iter().flat_map(|residue| residue.atoms.clone());
+
+                // Summed occupancy of each alternate location
+                let mut occupancies: BTreeMap<char, f32> = BTreeMap::new();
+                for atom in atoms.clone().map(|i| &structure.atoms[i]) {
+                    if let Some(alt_id) = atom.alt_id {
+                        *occupancies.entry(alt_id).or_default() += atom.occupancy.unwrap_or(1.0);
+                    }
+                }
+
+                // Ties go to the first id in alphabetical order
+                let highest = occupancies
+                    .iter()
+                    .max_by(|(id_a, a), (id_b, b)| a.total_cmp(b).then(id_b.cmp(id_a)))
+                    .map(|(id, _)| *id);
+                let picked = match policy {
+                    AltLocPolicy::Id(id) if occupancies.contains_key(&id) => Some(id),
+                    _ => highest,
+                };
+
+                for i in atoms {
+                    if let Some(alt_id) = structure.atoms[i].alt_id {
+                        visible[i] &= picked == Some(alt_id);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn tessellate(
+        &mut self,
+        structure: &Structure,
+        camera_front: Vec3,
+        view: &ViewOptions,
+    ) -> (Vec<ShapeGroup>, Vec3, Vec3) {
+        let mut visible = vec![view.all_frames; structure.atoms.len()];
+        for i in structure.frame_atoms(view.frame) {
+            visible[i] = true;
+        }
+        Self::select_altlocs(structure, view.altloc, &mut visible);
+
+        let b_factors = structure.atoms.iter().zip(&visible);
+        let b_factors = b_factors.filter_map(|(atom, v)| atom.b_factor.filter(|_| *v));
+        self.b_factor_range = b_factors.fold((f32::MAX, f32::MIN), |(min, max), b| {
+            (min.min(b), max.max(b))
+        });
+
+        // When every alternate location is shown, they're drawn see through on their own
+        let see_through: Vec<bool> = structure
+            .atoms
+            .iter()
+            .map(|atom| view.altloc == AltLocPolicy::All && atom.alt_id.is_some())
+            .collect();
+
+        let mut groups: Vec<ShapeGroup> = Vec::new();
+        let mut bounding_min = Vec3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut bounding_max = Vec3::new(f32::MIN, f32::MIN, f32::MIN);
+
+        let chain_groups = Self::chain_groups(structure, view, &visible);
+        for ((atoms, transforms), transparent) in
+            chain_groups.iter().cartesian_product([false, true])
+        {
+            let owned: Vec<bool> = atoms
+                .iter()
+                .zip(&see_through)
+                .map(|(v, s)| *v && *s == transparent)
+                .collect();
+            let (shapes, min, max) = match view.style {
+                RenderStyle::BallAndStick | RenderStyle::Wireframe => {
+                    self.wireframe(structure, (&owned, atoms), camera_front, view)
+                }
+                RenderStyle::SpaceFilling => {
+                    self.space_filling(structure, &owned, &view.color_scheme)
+                }
+                RenderStyle::Cartoon => {
+                    self.cartoon(structure, (&owned, atoms), camera_front, view)
+                }
+            };
+            if shapes.is_empty() {
+                continue;
+            }
+
+            // The bounds of every copy of the group
+            for transform in transforms {
+                for i in 0..8 {
+                    let corner =
+                        Vec3::select(BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0), max, min);
+                    let corner = transform.transform_point3(corner);
+                    bounding_min = bounding_min.min(corner);
+                    bounding_max = bounding_max.max(corner);
+                }
+            }
+            groups.push(ShapeGroup {
+                shapes,
+                transforms: transforms.clone(),
+                opacity: if transparent { ALTLOC_OPACITY } else { 1.0 },
+            });
+        }
+
+        let frame = structure.frames.get(view.frame);
+        if let Some(cell) = frame.and_then(|f| f.cell) {
+            let mut shapes = Vec::new();
+            Self::add_cell(&mut shapes, cell);
+            for shape in &shapes {
+                bounding_min = bounding_min.min(shape.bounds().0);
+                bounding_max = bounding_max.max(shape.bounds().1);
+            }
+            groups.push(ShapeGroup {
+                shapes,
+                transforms: vec![Mat4::IDENTITY],
+                opacity: 1.0,
+            });
+        }
+
+        (groups, bounding_min, bounding_max)
     }
 }
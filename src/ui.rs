@@ -2,14 +2,30 @@ use egui_wgpu::{RendererOptions, ScreenDescriptor};
 use wgpu::{CommandEncoder, Device, Queue, TextureFormat, TextureView};
 use winit::{event::WindowEvent, window::Window};
 
-use crate::tessellate::RenderStyle;
+use crate::tessellate::{AltLocPolicy, ColorScheme, RenderStyle, ViewOptions};
+
+// Summary of the selected record
+#[derive(Default)]
+pub struct RecordInfo {
+    pub name: String,
+    pub properties: Vec<(String, String)>,
+    pub frames: Vec<Vec<(String, String)>>, // properties of each frame
+    pub assemblies: Vec<String>,
+    pub alt_ids: Vec<char>,       // alternate locations of the atoms
+    pub diagnostics: Vec<String>, // problems with the file that didn't stop it from loading
+}
 
 pub struct UIState {
     pub file_path: String,
     pub path_changed: bool,
+    pub add_hydrogens: bool, // from the chemical component dictionary, when loading the file
     pub error_message: Option<String>,
-    pub view_type: RenderStyle,
+    pub view: ViewOptions,
     pub view_changed: bool,
+    pub record_index: usize,
+    pub record_count: usize,
+    pub record_changed: bool,
+    pub record: RecordInfo,
     pub fps: f32,
 }
 
@@ -91,11 +107,11 @@ impl DebugUI {
                 ui.horizontal(|h_ui| {
                     h_ui.label("View type");
                     egui::ComboBox::from_id_salt("combo")
-                        .selected_text(state.view_type.to_string())
+                        .selected_text(state.view.style.to_string())
                         .show_ui(h_ui, |combo_ui| {
                             state.view_changed |= combo_ui
                                 .selectable_value(
-                                    &mut state.view_type,
+                                    &mut state.view.style,
                                     RenderStyle::BallAndStick,
                                     RenderStyle::BallAndStick.to_string(),
                                 )
@@ -103,7 +119,7 @@ impl DebugUI {
 
                             state.view_changed |= combo_ui
                                 .selectable_value(
-                                    &mut state.view_type,
+                                    &mut state.view.style,
                                     RenderStyle::Wireframe,
                                     RenderStyle::Wireframe.to_string(),
                                 )
@@ -111,13 +127,184 @@ impl DebugUI {
 
                             state.view_changed |= combo_ui
                                 .selectable_value(
-                                    &mut state.view_type,
+                                    &mut state.view.style,
                                     RenderStyle::SpaceFilling,
                                     RenderStyle::SpaceFilling.to_string(),
                                 )
                                 .clicked();
+
+                            state.view_changed |= combo_ui
+                                .selectable_value(
+                                    &mut state.view.style,
+                                    RenderStyle::Cartoon,
+                                    RenderStyle::Cartoon.to_string(),
+                                )
+                                .clicked();
                         });
                 });
+
+                ui.horizontal(|h_ui| {
+                    h_ui.label("Color by");
+                    egui::ComboBox::from_id_salt("color_combo")
+                        .selected_text(state.view.color_scheme.to_string())
+                        .show_ui(h_ui, |combo_ui| {
+                            let schemes = [
+                                ColorScheme::Element,
+                                ColorScheme::Charge,
+                                ColorScheme::BFactor,
+                                ColorScheme::Occupancy,
+                            ];
+                            for scheme in schemes {
+                                state.view_changed |= combo_ui
+                                    .selectable_value(
+                                        &mut state.view.color_scheme,
+                                        scheme,
+                                        scheme.to_string(),
+                                    )
+                                    .clicked();
+                            }
+                        });
+                });
+
+                state.view_changed |= ui
+                    .checkbox(&mut state.view.show_interactions, "Hydrogen bonds")
+                    .changed();
+
+                // The hydrogens are added when parsing, so the file needs to be reloaded
+                state.path_changed |= ui
+                    .checkbox(&mut state.add_hydrogens, "Add missing hydrogens")
+                    .changed();
+
+                // Step through the molecules of a multi-record file
+                if state.record_count > 1 {
+                    ui.horizontal(|h_ui| {
+                        if h_ui.button("<").clicked() && state.record_index > 0 {
+                            state.record_index -= 1;
+                            state.record_changed = true;
+                        }
+                        h_ui.label(format!(
+                            "Molecule {}/{}",
+                            state.record_index + 1,
+                            state.record_count
+                        ));
+                        if h_ui.button(">").clicked() && state.record_index + 1 < state.record_count
+                        {
+                            state.record_index += 1;
+                            state.record_changed = true;
+                        }
+                    });
+                }
+
+                if !state.record.name.is_empty() {
+                    ui.label(&state.record.name);
+                }
+
+                // Biological assemblies, or the asymmetric unit as it is in the file
+                if !state.record.assemblies.is_empty() {
+                    let label = |assembly: Option<usize>| match assembly {
+                        Some(i) => format!("Assembly {}", state.record.assemblies[i]),
+                        None => String::from("Asymmetric unit"),
+                    };
+                    ui.horizontal(|h_ui| {
+                        h_ui.label("Show");
+                        egui::ComboBox::from_id_salt("assembly_combo")
+                            .selected_text(label(state.view.assembly))
+                            .show_ui(h_ui, |combo_ui| {
+                                let options = std::iter::once(None)
+                                    .chain((0..state.record.assemblies.len()).map(Some));
+                                for assembly in options {
+                                    state.view_changed |= combo_ui
+                                        .selectable_value(
+                                            &mut state.view.assembly,
+                                            assembly,
+                                            label(assembly),
+                                        )
+                                        .clicked();
+                                }
+                            });
+                    });
+                }
+
+                // Residues modeled in more than one conformation
+                if !state.record.alt_ids.is_empty() {
+                    ui.horizontal(|h_ui| {
+                        h_ui.label("Alternate locations");
+                        egui::ComboBox::from_id_salt("altloc_combo")
+                            .selected_text(state.view.altloc.to_string())
+                            .show_ui(h_ui, |combo_ui| {
+                                let ids = state.record.alt_ids.iter().map(|c| AltLocPolicy::Id(*c));
+                                let policies = [AltLocPolicy::HighestOccupancy, AltLocPolicy::All]
+                                    .into_iter()
+                                    .chain(ids);
+                                for policy in policies {
+                                    state.view_changed |= combo_ui
+                                        .selectable_value(
+                                            &mut state.view.altloc,
+                                            policy,
+                                            policy.to_string(),
+                                        )
+                                        .clicked();
+                                }
+                            });
+                    });
+                }
+
+                // Step through the frames of a trajectory
+                let num_frames = state.record.frames.len();
+                if num_frames > 1 {
+                    ui.horizontal(|h_ui| {
+                        h_ui.label("Frame");
+                        state.view_changed |= h_ui
+                            .add_enabled(
+                                !state.view.all_frames,
+                                egui::Slider::new(&mut state.view.frame, 0..=num_frames - 1),
+                            )
+                            .changed();
+                    });
+                    state.view_changed |= ui
+                        .checkbox(&mut state.view.all_frames, "Superpose all frames")
+                        .changed();
+                }
+
+                let frame_properties = state.record.frames.get(state.view.frame);
+                let properties: Vec<&(String, String)> = state
+                    .record
+                    .properties
+                    .iter()
+                    .chain(frame_properties.into_iter().flatten())
+                    .collect();
+
+                if !properties.is_empty() {
+                    egui::CollapsingHeader::new("Properties").show(ui, |c_ui| {
+                        egui::ScrollArea::vertical()
+                            .max_height(200.0)
+                            .show(c_ui, |s_ui| {
+                                egui::Grid::new("properties")
+                                    .striped(true)
+                                    .show(s_ui, |g_ui| {
+                                        for (name, value) in &properties {
+                                            g_ui.label(name);
+                                            g_ui.label(value);
+                                            g_ui.end_row();
+                                        }
+                                    });
+                            });
+                    });
+                }
+
+                let diagnostics = &state.record.diagnostics;
+                if !diagnostics.is_empty() {
+                    let title = format!("Diagnostics ({})", diagnostics.len());
+                    egui::CollapsingHeader::new(title).show(ui, |c_ui| {
+                        egui::ScrollArea::vertical()
+                            .max_height(200.0)
+                            .show(c_ui, |s_ui| {
+                                for message in diagnostics {
+                                    s_ui.label(message);
+                                }
+                            });
+                    });
+                }
             });
     }
 